@@ -0,0 +1,208 @@
+//! Understands the text printed by the adventure game of the challenge.
+
+use crate::equation::Equation;
+
+const NUMBERS: [&str; 10] = ["one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten"];
+const SHAPES: [(&str, i64); 6] = [
+    ("triangle", 3), ("square", 4), ("pentagon", 5), ("hexagon", 6), ("heptagon", 7), ("octagon", 8),
+];
+
+/// A room as the game describes it after a move or `look`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Room {
+    pub title: String,
+    /// The lines under the heading, up to the first empty one.
+    pub description: String,
+    /// The list of "Things of interest here".
+    pub items: Vec<String>,
+    pub exits: Vec<String>,
+}
+
+/// Returns the title of a room heading, e.g. `Foothills` for `== Foothills ==`.
+pub fn room_title(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("==")
+        .and_then(|title| title.strip_suffix("=="))
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+}
+
+/// Splits the output into the sections under the headings: the title and the following lines
+/// up to the first empty one.
+pub fn sections(text: &str) -> Vec<(&str, String)> {
+    let mut sections: Vec<(&str, String)> = Vec::new();
+    let mut inside = false;
+    for line in text.lines() {
+        if let Some(title) = room_title(line) {
+            sections.push((title, String::new()));
+            inside = true;
+        } else if line.trim().is_empty() {
+            inside = false;
+        } else if let (true, Some((_, section))) = (inside, sections.last_mut()) {
+            if !section.is_empty() {
+                section.push('\n');
+            }
+            section.push_str(line.trim());
+        }
+    }
+
+    sections
+}
+
+/// Returns the last room described in the output.
+pub fn parse_room(text: &str) -> Option<Room> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let start = lines.iter().rposition(|line| room_title(line).is_some())?;
+    let mut room = Room { title: room_title(lines[start])?.to_string(), ..Room::default() };
+
+    let mut lines = lines[start + 1..].iter();
+    room.description = lines.by_ref().take_while(|line| !line.is_empty()).copied().collect::<Vec<&str>>().join("\n");
+    let mut list: Option<&mut Vec<String>> = None;
+    for line in lines {
+        if line.starts_with("Things of interest here") {
+            list = Some(&mut room.items);
+        } else if line.starts_with("There is 1 exit") || (line.starts_with("There are") && line.contains("exits")) {
+            list = Some(&mut room.exits);
+        } else if let (Some(item), Some(list)) = (line.strip_prefix("- "), list.as_mut()) {
+            list.push(item.trim().to_string());
+        } else if !line.is_empty() {
+            list = None;
+        }
+    }
+
+    Some(room)
+}
+
+/// Returns the items listed by the last `inv` in the output, `None` if there is no list.
+pub fn parse_inventory(text: &str) -> Option<Vec<String>> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let start = lines.iter().rposition(|line| line.starts_with("Your inventory"))?;
+
+    Some(lines[start + 1..].iter()
+        .skip_while(|line| line.is_empty())
+        .map_while(|line| line.strip_prefix("- "))
+        .map(|item| item.trim().to_string())
+        .collect())
+}
+
+/// Returns the number an item shows: the dots, e.g. `It has two dots on one side`, or the sides
+/// of a shape, e.g. `a triangle`.
+pub fn description_value(description: &str) -> Option<i64> {
+    let words: Vec<String> = description.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    words.windows(2)
+        .find_map(|pair| match pair[1].as_str() {
+            "dot" | "dots" => NUMBERS.iter().position(|number| *number == pair[0]).map(|index| index as i64 + 1)
+                .or_else(|| pair[0].parse().ok()),
+            _ => None,
+        })
+        .or_else(|| words.iter().find_map(|word| SHAPES.iter()
+            .find(|(shape, _)| word.trim_end_matches('s') == *shape)
+            .map(|&(_, sides)| sides)))
+}
+
+/// Returns the first line of the output that is an equation with slots, e.g. the monument.
+pub fn find_equation(text: &str) -> Option<Equation> {
+    text.lines()
+        .filter(|line| line.contains('_') && line.contains('='))
+        .find_map(|line| line.parse().ok())
+}
+
+/// Returns the items described in the output that show a number, see [`description_value`].
+///
+/// An item described several times keeps the last value.
+pub fn find_items(text: &str) -> Vec<(String, i64)> {
+    let mut items: Vec<(String, i64)> = Vec::new();
+    for (title, description) in sections(text) {
+        if let Some(value) = description_value(&description) {
+            items.retain(|(name, _)| name != title);
+            items.push((title.to_string(), value));
+        }
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_title() {
+        assert_eq!(room_title("== Foothills =="), Some("Foothills"));
+        assert_eq!(room_title("  == Twisty passages ==\r"), Some("Twisty passages"));
+        assert_eq!(room_title("===="), None);
+        assert_eq!(room_title("What do I do?"), None);
+    }
+
+    #[test]
+    fn test_parse_room() {
+        let output = "== Foothills ==\n\
+            You find yourself standing at the base of an enormous mountain.\n\
+            \n\
+            Things of interest here:\n\
+            - tablet\n\
+            \n\
+            There are 3 exits:\n\
+            - doorway\n\
+            - south\n\
+            - north\n\
+            \n\
+            What do I do?\n\
+            == Dark cave ==\n\
+            This seems to be the mouth of a deep cave.\n\
+            As you peer north, you see a strange light.\n\
+            \n\
+            There is 1 exit:\n\
+            - south\n\
+            \n\
+            What do I do?\n";
+
+        assert_eq!(parse_room(output), Some(Room {
+            title: String::from("Dark cave"),
+            description: String::from("This seems to be the mouth of a deep cave.\nAs you peer north, you see a strange light."),
+            items: Vec::new(),
+            exits: vec![String::from("south")],
+        }));
+        let room = parse_room(&output[..output.find("== Dark").unwrap_or_default()]).expect("It is a room");
+        assert_eq!((room.items, room.exits.len()), (vec![String::from("tablet")], 3));
+        assert_eq!(parse_room("What do I do?"), None);
+    }
+
+    #[test]
+    fn test_parse_inventory() {
+        assert_eq!(parse_inventory("Your inventory:\n- tablet\n- empty lantern\n\nWhat do I do?\n"),
+                   Some(vec![String::from("tablet"), String::from("empty lantern")]));
+        assert_eq!(parse_inventory("Your inventory:\n\nWhat do I do?\n"), Some(Vec::new()));
+        assert_eq!(parse_inventory("== Foothills ==\n"), None);
+    }
+
+    #[test]
+    fn test_description_value() {
+        assert_eq!(description_value("This coin is made of a red metal.  It has two dots on one side."), Some(2));
+        assert_eq!(description_value("It has a triangle on one side."), Some(3));
+        assert_eq!(description_value("It has 12 dots."), Some(12));
+        assert_eq!(description_value("It is made of a blue metal."), None);
+    }
+
+    #[test]
+    fn test_monument() {
+        let output = "== Ruins ==\n\
+            There is a strange monument in the center of the hall with circular slots and unusual symbols.  It reads:\n\
+            \n\
+            _ + _ * _^2 + _^3 - _ = 399\n\
+            \n\
+            What do I do?\n\
+            == red coin ==\n\
+            This coin is made of a red metal.  It has two dots on one side.\n\
+            \n\
+            == shiny coin ==\n\
+            This coin is somehow still quite shiny.  It has a pentagon on one side.\n";
+
+        assert_eq!(find_equation(output).map(|equation| equation.slots()), Some(5));
+        assert_eq!(find_items(output), [(String::from("red coin"), 2), (String::from("shiny coin"), 5)]);
+    }
+}
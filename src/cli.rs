@@ -0,0 +1,217 @@
+use synacor_vm::debugger::parse_number;
+
+pub const USAGE: &str = r#"Usage: synacor-vm [OPTIONS] [BINARY]
+
+Runs a Synacor binary, 'challenge.bin' by default, in the debugger.
+
+Options:
+    --script <FILE>         reads the game input from a file or a transcript, then from the keyboard
+    --echo                  copies the script lines to the output, as if they were typed
+    --load-snapshot <FILE>  starts from a saved state, the binary isn't loaded
+    --patch <FILE>          applies a patch file to the loaded binary, can be repeated
+    --saves <DIR>           keeps the named save slots in the directory, 'saves' by default
+    --trace                 logs every executed instruction
+    --break <ADDR>          sets a breakpoint, can be repeated
+    --headless              runs the program without the debugger, the game input is read
+                            from the script or the standard input (a file or a pipe)
+    --output <FILE>         writes the program output to a file instead of the standard output
+    --transcript <FILE>     writes the program input and output with the time and the step
+    --record <FILE>         writes every input and output character with its step, for --replay
+    --replay <FILE>         replays a recording and checks that the output matches
+    --fast-forward <STEP>   stops the replay before the step and continues in the debugger
+    --expect <FILE>         runs headlessly and compares the output with a golden transcript
+    --codes <FILE>          collects the challenge codes from the output into a file
+    --map <FILE>            writes the rooms visited, as JSON for a .json file, as a Graphviz graph otherwise
+    --max-steps <N>         stops after N instructions
+    --help                  shows this help
+
+Exit codes in the headless mode:
+    0 - the program has halted
+    1 - the program has faulted
+    2 - the step limit is reached
+    3 - the game input has ended
+    4 - the output differs from the golden transcript or the recording"#;
+
+/// The command-line options.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub binary: String,
+    pub script: Option<String>,
+    pub echo: bool,
+    pub snapshot: Option<String>,
+    pub patches: Vec<String>,
+    pub saves: Option<String>,
+    pub trace: bool,
+    pub breakpoints: Vec<u16>,
+    pub headless: bool,
+    pub output: Option<String>,
+    pub transcript: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub fast_forward: Option<u64>,
+    pub expect: Option<String>,
+    pub codes: Option<String>,
+    pub map: Option<String>,
+    pub max_steps: Option<u64>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            binary: String::from("challenge.bin"),
+            script: None,
+            echo: false,
+            snapshot: None,
+            patches: Vec::new(),
+            saves: None,
+            trace: false,
+            breakpoints: Vec::new(),
+            headless: false,
+            output: None,
+            transcript: None,
+            record: None,
+            replay: None,
+            fast_forward: None,
+            expect: None,
+            codes: None,
+            map: None,
+            max_steps: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments without the program name.
+    pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut binary = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--script" => options.script = Some(value_of(&arg, args.next())?),
+                "--echo" => options.echo = true,
+                "--load-snapshot" => options.snapshot = Some(value_of(&arg, args.next())?),
+                "--patch" => options.patches.push(value_of(&arg, args.next())?),
+                "--saves" => options.saves = Some(value_of(&arg, args.next())?),
+                "--trace" => options.trace = true,
+                "--break" => {
+                    let address = value_of(&arg, args.next())?;
+                    options.breakpoints.push(parse_number(&address)
+                        .ok_or_else(|| format!("'{}' is not an address", address))?);
+                }
+                "--headless" => options.headless = true,
+                "--output" => options.output = Some(value_of(&arg, args.next())?),
+                "--transcript" => options.transcript = Some(value_of(&arg, args.next())?),
+                "--record" => options.record = Some(value_of(&arg, args.next())?),
+                "--replay" => options.replay = Some(value_of(&arg, args.next())?),
+                "--fast-forward" => {
+                    let step = value_of(&arg, args.next())?;
+                    options.fast_forward = Some(step.parse::<u64>()
+                        .map_err(|_| format!("'{}' is not a step", step))?);
+                }
+                "--expect" => {
+                    options.expect = Some(value_of(&arg, args.next())?);
+                    options.headless = true;
+                }
+                "--codes" => options.codes = Some(value_of(&arg, args.next())?),
+                "--map" => options.map = Some(value_of(&arg, args.next())?),
+                "--max-steps" => {
+                    let steps = value_of(&arg, args.next())?;
+                    options.max_steps = Some(steps.parse::<u64>()
+                        .map_err(|_| format!("'{}' is not a number of steps", steps))?);
+                }
+                "--help" | "-h" => options.help = true,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+                _ if binary.is_none() => binary = Some(arg),
+                _ => return Err(format!("Unexpected argument '{}'", arg)),
+            }
+        }
+
+        if options.fast_forward.is_some() && options.replay.is_none() {
+            return Err(String::from("'--fast-forward' expects '--replay'"));
+        }
+        if let Some(binary) = binary {
+            options.binary = binary;
+        }
+
+        Ok(options)
+    }
+}
+
+fn value_of(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("'{}' expects a value", option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_default() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let options = parse(&["--script", "walkthrough.txt", "--trace", "--break", "0x1234",
+            "--break", "17", "--echo", "--headless", "--output", "out.txt", "--transcript", "session.log",
+            "--max-steps", "1000", "--codes", "codes.txt", "--map", "map.json",
+            "--load-snapshot", "vault.snap", "--patch", "teleporter.patch", "--saves", "states", "other.bin"]).expect("The options are valid");
+
+        assert_eq!(options, Options {
+            binary: String::from("other.bin"),
+            script: Some(String::from("walkthrough.txt")),
+            echo: true,
+            snapshot: Some(String::from("vault.snap")),
+            patches: vec![String::from("teleporter.patch")],
+            saves: Some(String::from("states")),
+            trace: true,
+            breakpoints: vec![0x1234, 17],
+            headless: true,
+            output: Some(String::from("out.txt")),
+            transcript: Some(String::from("session.log")),
+            record: None,
+            replay: None,
+            fast_forward: None,
+            expect: None,
+            codes: Some(String::from("codes.txt")),
+            map: Some(String::from("map.json")),
+            max_steps: Some(1000),
+            help: false,
+        });
+    }
+
+    #[test]
+    fn test_parse_expect() {
+        let options = parse(&["--expect", "walkthrough.out"]).expect("The options are valid");
+
+        assert_eq!(options.expect, Some(String::from("walkthrough.out")));
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn test_parse_replay() {
+        let options = parse(&["--record", "new.rec", "--replay", "old.rec", "--fast-forward", "5000"])
+            .expect("The options are valid");
+
+        assert_eq!(options.record, Some(String::from("new.rec")));
+        assert_eq!(options.replay, Some(String::from("old.rec")));
+        assert_eq!(options.fast_forward, Some(5000));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--script"]).is_err());
+        assert!(parse(&["--break", "here"]).is_err());
+        assert!(parse(&["--max-steps", "-1"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--fast-forward", "10"]).is_err());
+        assert!(parse(&["one.bin", "two.bin"]).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Collects the codes of the challenge from the output of the program.
+//!
+//! A code is a word of 12 letters and digits that doesn't look like an English word, e.g. with
+//! capital letters inside, printed near a phrase like "code" or "chiseled". The last code is seen
+//! in a mirror, so its mirror image is collected as well, see [`mirror`].
+//!
+//! The collected codes are kept in a file, one per line with the step and the line it was found in:
+//!
+//! ```text
+//! QdrXTGBDdCsw 2154 The self-test completion code is: QdrXTGBDdCsw
+//! ```
+
+use crate::vm::Observer;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The length of every code.
+pub const CODE_LENGTH: usize = 12;
+
+// The phrases that precede the codes in the game
+const KEYWORDS: &[&str] = &["code", "chiseled", "writing", "it says", "mirror"];
+// A code is accepted when a keyword is in the same line or in one of the previous lines
+const CONTEXT_LINES: usize = 3;
+
+/// A code found in the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Code {
+    pub code: String,
+    /// The number of instructions executed when the line with the code was completed.
+    pub step: u64,
+    /// The line with the code.
+    pub context: String,
+}
+
+/// Returns `true` if the word can be a code.
+pub fn is_code(word: &str) -> bool {
+    word.len() == CODE_LENGTH
+        && word.chars().all(|c| c.is_ascii_alphanumeric())
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+        && (word.chars().any(|c| c.is_ascii_digit()) || word.chars().skip(1).any(|c| c.is_ascii_uppercase()))
+}
+
+/// Returns the code as it reads in a mirror: the letters are reversed, `b` and `d`, `p` and `q`
+/// are swapped.
+pub fn mirror(code: &str) -> String {
+    code.chars()
+        .rev()
+        .map(|c| match c {
+            'b' => 'd',
+            'd' => 'b',
+            'p' => 'q',
+            'q' => 'p',
+            c => c,
+        })
+        .collect()
+}
+
+/// Finds the codes in the output, it observes the [`VirtualMachine`](crate::VirtualMachine).
+#[derive(Debug, Default)]
+pub struct CodeCollector {
+    codes: Vec<Code>,
+    path: Option<PathBuf>,
+    line: Vec<u8>,
+    recent: VecDeque<String>,
+}
+
+impl CodeCollector {
+    /// Reads the codes collected before from the file, the new codes are appended to it.
+    ///
+    /// The file is created with the first code.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let codes = text.lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let (code, step) = (parts.next()?, parts.next()?.parse().ok()?);
+                Some(Code { code: code.to_string(), step, context: parts.next().unwrap_or_default().to_string() })
+            })
+            .collect();
+
+        Ok(CodeCollector { codes, path: Some(path.as_ref().to_path_buf()), ..CodeCollector::default() })
+    }
+
+    /// Returns the codes in the order they were found.
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    fn add(&mut self, code: Code) {
+        if self.codes.iter().any(|known| known.code == code.code) {
+            return;
+        }
+
+        if let Some(path) = &self.path {
+            let written = OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{} {} {}", code.code, code.step, code.context));
+            if let Err(err) = written {
+                eprintln!("Couldn't write the code {} to '{}': {}", code.code, path.display(), err);
+            }
+        }
+        self.codes.push(code);
+    }
+
+    fn scan_line(&mut self, step: u64, line: String) {
+        let line = line.trim().to_string();
+        if line.is_empty() {
+            return;
+        }
+
+        self.recent.push_back(line.to_lowercase());
+        if self.recent.len() > CONTEXT_LINES + 1 {
+            self.recent.pop_front();
+        }
+        let near = |keyword: &&str| self.recent.iter().any(|recent| recent.contains(*keyword));
+        if !KEYWORDS.iter().any(near) {
+            return;
+        }
+        // Only the code in the same line is seen in the mirror
+        let mirrored = self.recent.back().is_some_and(|recent| recent.contains("mirror"));
+
+        let words: Vec<String> = line.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| is_code(word))
+            .map(str::to_string)
+            .collect();
+        for word in words {
+            if mirrored {
+                let context = format!("mirror image of {}", word);
+                self.add(Code { code: word.clone(), step, context: line.clone() });
+                self.add(Code { code: mirror(&word), step, context });
+            } else {
+                self.add(Code { code: word, step, context: line.clone() });
+            }
+        }
+    }
+}
+
+impl Observer for CodeCollector {
+    fn output(&mut self, step: u64, c: u8) {
+        if c == b'\n' {
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            self.scan_line(step, line);
+        } else {
+            self.line.push(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(collector: &mut CodeCollector, text: &str) {
+        text.bytes().enumerate().for_each(|(step, c)| collector.output(step as u64, c));
+    }
+
+    #[test]
+    fn test_is_code() {
+        assert!(is_code("QdrXTGBDdCsw"));
+        assert!(is_code("abcdef123456"));
+        assert!(!is_code("Occasionally"));
+        assert!(!is_code("instructions"));
+        assert!(!is_code("QdrXTGBDdCs"));
+        assert!(!is_code("123456789012"));
+    }
+
+    #[test]
+    fn test_mirror() {
+        assert_eq!(mirror("bdpqXY12"), "21YXpqbd");
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut collector = CodeCollector::default();
+        collect(&mut collector, "The self-test completion code is: QdrXTGBDdCsw\n\
+            Chiseled on the wall of one of the passageways, you see:\n\
+            \n\
+            \x20   wMnLcRyrMgQx\n\
+            \n\
+            You take note of this and keep walking.\n\
+            Through the mirror, you see \"bpUdqHi8i1Ya\" scrawled in charcoal on your forehead.\n\
+            The self-test completion code is: QdrXTGBDdCsw\n");
+
+        let codes: Vec<&str> = collector.codes().iter().map(|code| code.code.as_str()).collect();
+        assert_eq!(codes, ["QdrXTGBDdCsw", "wMnLcRyrMgQx", "bpUdqHi8i1Ya", "aY1i8iHpbUqd"]);
+        assert_eq!(collector.codes()[0].step, 46);
+        assert_eq!(collector.codes()[1].context, "wMnLcRyrMgQx");
+        assert_eq!(collector.codes()[3].context, "mirror image of bpUdqHi8i1Ya");
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join(format!("synacor-vm-codes-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut collector = CodeCollector::open(&path).expect("A missing file is empty");
+        collect(&mut collector, "The self-test completion code is: QdrXTGBDdCsw\n");
+        let mut collector = CodeCollector::open(&path).expect("The file must be readable");
+        assert_eq!(collector.codes(), [Code {
+            code: String::from("QdrXTGBDdCsw"),
+            step: 46,
+            context: String::from("The self-test completion code is: QdrXTGBDdCsw"),
+        }]);
+
+        collect(&mut collector, "The self-test completion code is: QdrXTGBDdCsw\nThis code: abcdef123456\n");
+        let text = fs::read_to_string(&path).expect("The file must be readable");
+        assert_eq!(text.lines().count(), 2);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -11,45 +11,64 @@ Things of interest here:
 - shiny coin    == 5
 - concave coin  == 7
 - blue coin     == 9
+
+Without arguments the solver uses the monument and the coins above. Other variants of the challenge
+use other equations and values: pass a file with the output of the game, the first line with slots
+is the equation and the items are read from their descriptions (`look red coin`), or with lines like
+`red coin = 2`.
  */
 
-use itertools::Itertools;
+use std::env;
+use std::fs;
+use std::process::exit;
 
-const EXPRESSION_RESULT: i32 = 399;
+use synacor_vm::adventure::{find_equation, find_items};
+use synacor_vm::equation::Equation;
 
-#[inline]
-fn calc_expression(a: i32, b: i32, c: i32, d: i32, e: i32) -> i32 {
-    a + b * c.pow(2) + d.pow(3) - e
-}
+const MONUMENT: &str = "_ + _ * _^2 + _^3 - _ = 399";
+const COINS: [(&str, i64); 5] = [
+    ("red coin", 2), ("corroded coin", 3), ("shiny coin", 5), ("concave coin", 7), ("blue coin", 9),
+];
 
-fn get_coin(coin: i32) -> &'static str {
-    match coin {
-        2 => "red coin",
-        3 => "corroded coin",
-        5 => "shiny coin",
-        7 => "concave coin",
-        9 => "blue coin",
+// Reads the equation and the items from the output of the game or from a list of values
+fn read_puzzle(text: &str) -> Result<(Equation, Vec<(String, i64)>), String> {
+    let equation = find_equation(text).ok_or("Couldn't find an equation with slots")?;
 
-        _ => "unknown coin"
+    let mut items = find_items(text);
+    for line in text.lines().filter(|line| !line.contains('_')) {
+        if let Some((name, value)) = line.split_once('=') {
+            if let (name, Ok(value)) = (name.trim().trim_start_matches('-').trim(), value.trim().parse()) {
+                items.retain(|(item, _)| item != name);
+                items.push((name.to_string(), value));
+            }
+        }
     }
+
+    Ok((equation, items))
 }
 
 fn main() {
-    let input: Vec<i32> = vec![2, 3, 5, 7, 9];
-
-    let result = input.into_iter()
-        .permutations(5)
-        .find(|perm| {
-            calc_expression(perm[0], perm[1], perm[2], perm[3], perm[4]) == EXPRESSION_RESULT
-        });
-
-    if let Some(coins) = result {
-        println!("The answer is: {:?}. They are", coins);
-        coins.iter()
-            .for_each(|&coin|
-                println!("- {}", get_coin(coin))
-            );
-    } else {
+    let (equation, items) = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(&path)
+            .map_err(|err| format!("Couldn't read '{}': {}", path, err))
+            .and_then(|text| read_puzzle(&text))
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                exit(65);
+            }),
+        None => (
+            MONUMENT.parse().expect("The monument is a valid equation"),
+            COINS.iter().map(|&(name, value)| (name.to_string(), value)).collect(),
+        ),
+    };
+
+    let solutions = equation.solve(&items);
+    if solutions.is_empty() {
         println!("Sorry, couldn't find an answer");
     }
+    for solution in solutions {
+        let values: Vec<i64> = solution.iter().map(|(_, value)| *value).collect();
+        println!("The answer is: {:?}. They are", values);
+        solution.iter().for_each(|(name, _)| println!("- {}", name));
+    }
 }
@@ -1,16 +1,61 @@
 use crate::mem::{Memory, MAX_ADDRESS};
+use crate::snapshot::Snapshot;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
 use log::trace;
 
+/// The number of registers of the CPU, they are addressed as `32768..=32775` in the bytecode.
 pub const MAX_REGISTERS: usize = 8;
 
+/// Errors reported by the [`CPU`] while executing the bytecode.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CPUError {
+    /// The address is neither a memory address nor a register.
     OverflowAddress(u16),
+    /// The register number is out of `0..MAX_REGISTERS`.
     OverflowRegister(u8),
+    /// `pop` was executed on the empty stack.
     PopFromEmptyStack,
+    /// The word at `address` is not a known instruction.
     UnknownOpCode { opcode: u16, address: u16 },
+    /// `mod` by zero at `address`.
+    DivisionByZero(u16),
+    /// Strict mode: the operand `value` of the instruction at `address` is above 32775.
+    InvalidOperand { address: u16, value: u16 },
+    /// Strict mode: the instruction at `address` must write to a register, but `value` isn't one.
+    TargetNotRegister { address: u16, value: u16 },
+    /// Strict mode: `out` got a value that is not an ASCII character.
+    InvalidCharacter(u16),
+    /// Strict mode: the program counter left the memory.
+    OverflowProgramCounter(u16),
+    /// Strict mode: the operands of the instruction at the address go past the end of the memory.
+    TruncatedInstruction(u16),
+    /// `in` was executed, but there is no input, see [`CPU::feed_input`].
+    EmptyInput,
+}
+
+// The number of operands of each instruction, the index is the opcode
+pub(crate) const OPERANDS: [u16; 22] = [0, 2, 1, 1, 3, 3, 1, 2, 2, 3, 3, 3, 3, 3, 2, 2, 2, 1, 0, 1, 1, 0];
+// The names of the instructions in the specification, the index is the opcode
+pub(crate) const MNEMONICS: [&str; 22] = [
+    "halt", "set", "push", "pop", "eq", "gt", "jmp", "jt", "jf", "add", "mult", "mod", "and", "or", "not",
+    "rmem", "wmem", "call", "ret", "out", "in", "noop",
+];
+
+/// A frame of the shadow call stack, it is pushed by `call` and popped by `ret`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// The address of the `call` instruction.
+    pub call_site: u16,
+    /// The address of the called function.
+    pub target: u16,
+    /// The registers `r0..=r2` at the moment of the call, the usual way to pass arguments.
+    pub arguments: [u16; 3],
+    // The length of the stack right after pushing the return address
+    pub(crate) depth: usize,
 }
 
 enum ExecutionResult {
@@ -19,25 +64,55 @@ enum ExecutionResult {
     Next(u16),
 }
 
+/// The CPU of the VM: eight registers, an unbounded stack and the program counter.
+///
+/// The memory is shared with the [`VirtualMachine`](crate::VirtualMachine) that owns the CPU.
+/// The CPU doesn't touch the terminal: `in` consumes the characters given to [`CPU::feed_input`]
+/// and `out` collects the characters for [`CPU::take_output`].
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     memory: Rc<RefCell<Memory>>,
     registers: [u16; MAX_REGISTERS],
     stack: Vec<u16>,
+    frames: Vec<Frame>,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
 
     current_address: u16,
+    strict: bool,
 }
 
 impl CPU {
+    /// Creates a CPU that executes the bytecode from `mem` starting at the address 0.
     pub fn new(mem: Rc<RefCell<Memory>>) -> CPU {
         CPU {
             memory: mem,
             registers: [0; MAX_REGISTERS],
             stack: Vec::new(),
+            frames: Vec::new(),
+            input: VecDeque::new(),
+            output: Vec::new(),
 
             current_address: 0,
+            strict: false,
         }
     }
 
+    /// Enables or disables the strict spec-conformance mode.
+    ///
+    /// In strict mode the CPU faults on bytecode that is tolerated otherwise: operands above 32775,
+    /// non-register targets, non-ASCII output, the program counter leaving the memory, operands past
+    /// the end of the memory and failed writes in `in`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Returns `true` if the strict spec-conformance mode is enabled.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Reads a memory word (`0..=32767`) or a register (`32768..=32775`).
     pub fn get_value_from_address(&self, address: u16) -> Result<u16, CPUError> {
         match address {
             0..=0x7FFF => {
@@ -56,10 +131,39 @@ impl CPU {
         }
     }
 
+    /// Returns the address of the next instruction to execute.
     pub fn get_current_address(&self) -> u16 {
         self.current_address
     }
 
+    /// Moves the program counter to `address`.
+    pub fn set_current_address(&mut self, address: u16) {
+        self.current_address = address;
+    }
+
+    /// Returns the data stack, the top of the stack is the last element.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Pushes `value` onto the data stack, like `push`.
+    pub fn push_stack(&mut self, value: u16) {
+        self.stack.push(value);
+    }
+
+    /// Removes the top of the data stack, like `pop`, `None` if the stack is empty.
+    ///
+    /// Popping a return address drops its frame from the shadow call stack, so a native
+    /// replacement of a function returns with `cpu.pop_stack()`, see
+    /// [`VirtualMachine::add_hook`](crate::VirtualMachine::add_hook).
+    pub fn pop_stack(&mut self) -> Option<u16> {
+        let value = self.stack.pop();
+        self.unwind_frames();
+
+        value
+    }
+
+    /// Writes a memory word (`0..=32767`) or a register (`32768..=32775`), returns the old value.
     pub fn set_value_in_address(&mut self, address: u16, value: u16) -> Result<u16, CPUError> {
         match address {
             0..=0x7FFF => {
@@ -76,6 +180,64 @@ impl CPU {
         }
     }
 
+    /// Appends the characters to the input consumed by `in`.
+    pub fn feed_input(&mut self, data: &[u8]) {
+        self.input.extend(data);
+    }
+
+    /// Returns the number of characters that `in` hasn't consumed yet.
+    pub fn pending_input(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Returns the character that the next `in` consumes.
+    pub fn next_input(&self) -> Option<u8> {
+        self.input.front().copied()
+    }
+
+    /// Returns `true` if the next instruction is `in` and there is no input for it.
+    pub fn needs_input(&self) -> bool {
+        self.input.is_empty() && self.get_value_from_address(self.current_address).ok() == Some(20)
+    }
+
+    /// Returns and clears the characters written by `out`.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Copies the memory, the registers, the stacks, the pending input and the program counter.
+    pub(crate) fn save_state(&self, steps: u64) -> Snapshot {
+        Snapshot {
+            memory: self.memory.borrow().as_slice().to_vec(),
+            registers: self.registers,
+            stack: self.stack.clone(),
+            frames: self.frames.clone(),
+            input: self.input.iter().copied().collect(),
+            current_address: self.current_address,
+            steps,
+        }
+    }
+
+    /// Restores the state saved by [`CPU::save_state`].
+    pub(crate) fn load_state(&mut self, snapshot: &Snapshot) -> Result<(), CPUError> {
+        self.memory.borrow_mut()
+            .load_data(&snapshot.memory)
+            .or(Err(CPUError::OverflowAddress(snapshot.memory.len() as u16)))?;
+        self.registers = snapshot.registers;
+        self.stack = snapshot.stack.clone();
+        self.frames = snapshot.frames.clone();
+        self.input = snapshot.input.iter().copied().collect();
+        self.current_address = snapshot.current_address;
+
+        Ok(())
+    }
+
+    /// Returns the shadow call stack, the innermost frame is the last one.
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Reads the register `number`, `None` if there is no such register.
     pub fn read_register(&self, number: u8) -> Option<u16> {
         match number {
             0..=7 => Some(self.registers[number as usize]),
@@ -83,6 +245,7 @@ impl CPU {
         }
     }
 
+    /// Writes the register `number` and returns its old value.
     pub fn write_register(&mut self, number: u8, value: u16) -> Result<u16, CPUError> {
         match number {
             0..=7 => {
@@ -95,7 +258,7 @@ impl CPU {
         }
     }
 
-    fn from_raw_to_u16(&self, raw: u16) -> Result<u16, CPUError> {
+    fn raw_to_u16(&self, raw: u16) -> Result<u16, CPUError> {
         match raw {
             0..=0x7FFF => Ok(raw),
             0x8000..=0x8007 => {
@@ -109,12 +272,17 @@ impl CPU {
         }
     }
 
+    /// Executes a single instruction, returns `true` when the program has stopped.
     pub fn execute(&mut self) -> Result<bool, CPUError> {
         let op_code = self.get_value_from_address(self.current_address)?;
         let a = self.get_value_from_address(self.current_address + 1);
         let b = self.get_value_from_address(self.current_address + 2);
         let c = self.get_value_from_address(self.current_address + 3);
 
+        if self.strict {
+            self.check_operands(op_code)?;
+        }
+
         /*
                     {
                         let a = self.get_value_from_address(self.current_address + 1)?;
@@ -123,9 +291,9 @@ impl CPU {
 
                         println!("  Op: {:#02}, a: {:#06X} / {:#05}, b: {:#06X} / {:#05}, c: {:#06X} / {:#05}",
                                  op_code,
-                                 a, self.from_raw_to_u16(a)?,
-                                 b, self.from_raw_to_u16(b)?,
-                                 c, self.from_raw_to_u16(c)?,
+                                 a, self.raw_to_u16(a)?,
+                                 b, self.raw_to_u16(b)?,
+                                 c, self.raw_to_u16(c)?,
                         );
                     }
         */
@@ -165,9 +333,42 @@ impl CPU {
             ExecutionResult::Next(size) => self.current_address += size,
         };
 
+        if self.strict && self.current_address as usize >= MAX_ADDRESS {
+            return Err(CPUError::OverflowProgramCounter(self.current_address));
+        }
+
         Ok(false)
     }
 
+    // Validates the operands of the instruction at the current address for the strict mode
+    fn check_operands(&self, op_code: u16) -> Result<(), CPUError> {
+        let count = match OPERANDS.get(op_code as usize) {
+            Some(&count) => count,
+            None => return Ok(()), // the unknown opcode is reported by the execution
+        };
+        // Past the end of the memory the operands would be read from the registers
+        if self.current_address as usize + count as usize >= MAX_ADDRESS {
+            return Err(CPUError::TruncatedInstruction(self.current_address));
+        }
+
+        for offset in 1..=count {
+            let value = self.get_value_from_address(self.current_address + offset)?;
+            if value > 0x8007 {
+                return Err(CPUError::InvalidOperand { address: self.current_address, value });
+            }
+        }
+
+        // set, pop, eq, gt, add, mult, mod, and, or, not, rmem and in write to a register <a>
+        if let 1 | 3..=5 | 9..=15 | 20 = op_code {
+            let value = self.get_value_from_address(self.current_address + 1)?;
+            if get_registry_from_address(value).is_none() {
+                return Err(CPUError::TargetNotRegister { address: self.current_address, value });
+            }
+        }
+
+        Ok(())
+    }
+
     // halt: 0 - stop execution and terminate the program
     fn halt(&self) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: halt!", self.current_address);
@@ -179,7 +380,7 @@ impl CPU {
     fn set(&mut self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: set ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let b = self.from_raw_to_u16(raw_b)?;
+        let b = self.raw_to_u16(raw_b)?;
         trace!("          b: {:#06X}", b);
         let _ = self.set_value_in_address(raw_a, b)?;
 
@@ -190,7 +391,7 @@ impl CPU {
     fn push(&mut self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: push ({:#06X})", self.current_address, raw_a);
 
-        let a = self.from_raw_to_u16(raw_a)?;
+        let a = self.raw_to_u16(raw_a)?;
         self.stack.push(a);
 
         Ok(ExecutionResult::Next(2))
@@ -201,6 +402,7 @@ impl CPU {
         trace!("{:#06X}: pop ({:#06X})", self.current_address, raw_a);
 
         if let Some(value) = self.stack.pop() {
+            self.unwind_frames();
             self.set_value_in_address(raw_a, value)?;
 
             Ok(ExecutionResult::Next(2))
@@ -213,8 +415,8 @@ impl CPU {
     fn eq(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: eq ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
         trace!("          b: {:#06X}, c: {:#06X}", b, c);
 
         self.set_value_in_address(raw_a,
@@ -227,8 +429,8 @@ impl CPU {
     fn gt(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: gt ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
 
         self.set_value_in_address(raw_a,
                                   if b > c { 1 } else { 0 })?;
@@ -240,7 +442,7 @@ impl CPU {
     fn jmp(&self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: jmp ({:#06X})", self.current_address, raw_a);
 
-        let a = self.from_raw_to_u16(raw_a)?;
+        let a = self.raw_to_u16(raw_a)?;
         trace!("          a: {:#06X}", a);
 
         Ok(ExecutionResult::Jump(a))
@@ -250,8 +452,8 @@ impl CPU {
     fn jt(&self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: jt ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let a = self.from_raw_to_u16(raw_a)?;
-        let b = self.from_raw_to_u16(raw_b)?;
+        let a = self.raw_to_u16(raw_a)?;
+        let b = self.raw_to_u16(raw_b)?;
         trace!("          a: {:#06X}, b: {:#06X}", a, b);
 
         Ok(if a != 0 {
@@ -265,8 +467,8 @@ impl CPU {
     fn jf(&self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: jf ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let a = self.from_raw_to_u16(raw_a)?;
-        let b = self.from_raw_to_u16(raw_b)?;
+        let a = self.raw_to_u16(raw_a)?;
+        let b = self.raw_to_u16(raw_b)?;
         trace!("          a: {:#06X}, b: {:#06X}", a, b);
 
         Ok(if a == 0 {
@@ -280,8 +482,8 @@ impl CPU {
     fn add(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: add ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
 
         let sum = b.wrapping_add(c) & 0x7FFF;
         trace!("          b: {:#06X}, c: {:#06X}, res: {:#06X}", b, c, sum);
@@ -295,8 +497,8 @@ impl CPU {
     fn mult(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: mult ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
 
         let mult = b.wrapping_mul(c) & 0x7FFF;
         trace!("          b: {:#06X}, c: {:#06X}, res: {:#06X}", b, c, mult);
@@ -309,10 +511,11 @@ impl CPU {
     fn modulo(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: mod ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
 
-        let rem = b.wrapping_rem(c);
+        let rem = b.checked_rem(c)
+            .ok_or(CPUError::DivisionByZero(self.current_address))?;
         trace!("          b: {:#06X}, c: {:#06X}, res: {:#06X}", b, c, rem);
 
         self.set_value_in_address(raw_a, rem)?;
@@ -323,8 +526,8 @@ impl CPU {
     fn and(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: and ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
         let and = b & c;
         trace!("          b: {:#06X}, c: {:#06X}, res: {:#06X}", b, c, and);
 
@@ -336,8 +539,8 @@ impl CPU {
     fn or(&mut self, raw_a: u16, raw_b: u16, raw_c: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: or ({:#06X}, {:#06X}, {:#06X})", self.current_address, raw_a, raw_b, raw_c);
 
-        let b = self.from_raw_to_u16(raw_b)?;
-        let c = self.from_raw_to_u16(raw_c)?;
+        let b = self.raw_to_u16(raw_b)?;
+        let c = self.raw_to_u16(raw_c)?;
         let or = b | c;
         trace!("          b: {:#06X}, c: {:#06X}, res: {:#06X}", b, c, or);
 
@@ -349,7 +552,7 @@ impl CPU {
     fn not(&mut self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: not ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let b = self.from_raw_to_u16(raw_b)?;
+        let b = self.raw_to_u16(raw_b)?;
         let not = !b & 0x7FFF;
         trace!("          b: {:#06X}, res: {:#06X}", b, not);
 
@@ -361,7 +564,7 @@ impl CPU {
     fn rmem(&mut self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: rmem ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let b = self.from_raw_to_u16(raw_b)?;
+        let b = self.raw_to_u16(raw_b)?;
         let value = self.get_value_from_address(b)?;
 
         trace!("          b: {:#06X}, res: {:#06X}", b, value);
@@ -375,8 +578,8 @@ impl CPU {
     fn wmem(&mut self, raw_a: u16, raw_b: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: wmem ({:#06X}, {:#06X})", self.current_address, raw_a, raw_b);
 
-        let a = self.from_raw_to_u16(raw_a)?;
-        let b = self.from_raw_to_u16(raw_b)?;
+        let a = self.raw_to_u16(raw_a)?;
+        let b = self.raw_to_u16(raw_b)?;
 
         trace!("          a: {:#06X}, b: {:#06X}", a, b);
 
@@ -389,9 +592,15 @@ impl CPU {
     fn call(&mut self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: call ({:#06X})", self.current_address, raw_a);
 
-        let a = self.from_raw_to_u16(raw_a)?;
+        let a = self.raw_to_u16(raw_a)?;
 
         self.stack.push(self.current_address + 2);
+        self.frames.push(Frame {
+            call_site: self.current_address,
+            target: a,
+            arguments: [self.registers[0], self.registers[1], self.registers[2]],
+            depth: self.stack.len(),
+        });
         Ok(ExecutionResult::Jump(a))
     }
 
@@ -400,6 +609,7 @@ impl CPU {
         trace!("{:#06X}: ret", self.current_address);
 
         if let Some(a) = self.stack.pop() {
+            self.unwind_frames();
             Ok(ExecutionResult::Jump(a))
         } else {
             Ok(ExecutionResult::Stop)
@@ -407,15 +617,18 @@ impl CPU {
     }
 
     // out: 19 a - write the character represented by ascii code <a> to the terminal
-    fn out(&self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
+    fn out(&mut self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
         trace!("{:#06X}: out ({:#06X})", self.current_address, raw_a);
 
-        let a = self.from_raw_to_u16(raw_a)?;
+        let a = self.raw_to_u16(raw_a)?;
+        if self.strict && a > 0x7F {
+            return Err(CPUError::InvalidCharacter(a));
+        }
         let c = (a as u8) as char;
 
         trace!("          a: {:#06X}, res: {}", a, c);
 
-        print!("{}", c);
+        self.output.push(a as u8);
 
         Ok(ExecutionResult::Next(2))
     }
@@ -424,14 +637,15 @@ impl CPU {
     // assumed that once input starts, it will continue until a newline is encountered; this means
     // that you can safely read whole lines from the keyboard and trust that they will be fully read
     fn inp(&mut self, raw_a: u16) -> Result<ExecutionResult, CPUError> {
-        use std::io::Read;
+        trace!("{:#06X}: in ({:#06X})", self.current_address, raw_a);
 
-        if let Some(Ok(c)) = std::io::stdin().bytes().next() {
-            self.set_value_in_address(raw_a, c as u16);
-        } else {
-            self.set_value_in_address(raw_a, 0);
-        }
+        let c = self.input.pop_front().ok_or(CPUError::EmptyInput)?;
+        trace!("          res: {:#04X}", c);
 
+        let written = self.set_value_in_address(raw_a, c as u16);
+        if self.strict {
+            written?;
+        }
 
         Ok(ExecutionResult::Next(2))
     }
@@ -442,6 +656,13 @@ impl CPU {
 
         Ok(ExecutionResult::Next(1))
     }
+
+    // Drops the frames whose return address is no longer on the stack
+    fn unwind_frames(&mut self) {
+        while matches!(self.frames.last(), Some(frame) if frame.depth > self.stack.len()) {
+            self.frames.pop();
+        }
+    }
 }
 
 fn get_registry_from_address(address: u16) -> Option<u8> {
@@ -497,7 +718,7 @@ mod tests {
     #[test]
     fn test_read_register() {
         let mut cpu = CPU::new(Rc::new(RefCell::new(Memory::default())));
-        cpu.registers[..3].copy_from_slice(&vec![3, 4, 5]);
+        cpu.registers[..3].copy_from_slice(&[3, 4, 5]);
 
         assert_eq!(cpu.read_register(0), Some(3));
         assert_eq!(cpu.read_register(1), Some(4));
@@ -508,6 +729,160 @@ mod tests {
         assert_eq!(cpu.read_register(u8::MAX), None);
     }
 
+    fn cpu_with_program(program: &[u16]) -> CPU {
+        let mut mem = Memory::default();
+        mem.load_data(program).ok();
+
+        CPU::new(Rc::new(RefCell::new(mem)))
+    }
+
+    #[test]
+    fn test_lenient_mode() {
+        // set 100 5; add 101 r7 3; halt
+        let mut cpu = cpu_with_program(&[1, 100, 5, 9, 101, 32775, 3, 0]);
+
+        assert!(!cpu.execute().expect("A memory target is tolerated"));
+        assert_eq!(cpu.get_value_from_address(100).ok(), Some(5));
+        assert!(!cpu.execute().expect("A memory target is tolerated"));
+        assert_eq!(cpu.get_value_from_address(101).ok(), Some(3));
+        assert!(cpu.execute().expect("Halt must stop the program"));
+    }
+
+    #[test]
+    fn test_strict_target_not_register() {
+        let mut cpu = cpu_with_program(&[1, 100, 5]);
+        cpu.set_strict(true);
+
+        match cpu.execute() {
+            Err(CPUError::TargetNotRegister { address: 0, value: 100 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_invalid_operand() {
+        let mut cpu = cpu_with_program(&[6, 32776]);
+        cpu.set_strict(true);
+
+        match cpu.execute() {
+            Err(CPUError::InvalidOperand { address: 0, value: 32776 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_invalid_character() {
+        let mut cpu = cpu_with_program(&[19, 200]);
+        cpu.set_strict(true);
+
+        match cpu.execute() {
+            Err(CPUError::InvalidCharacter(200)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_program_counter_overflow() {
+        let mut program = vec![0; MAX_ADDRESS];
+        program[0] = 6; // jmp 0x7FFF
+        program[1] = 0x7FFF;
+        program[0x7FFF] = 21; // noop
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_strict(true);
+
+        assert!(!cpu.execute().expect("The jump is valid"));
+        match cpu.execute() {
+            Err(CPUError::OverflowProgramCounter(0x8000)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_truncated_instruction() {
+        let mut program = vec![0; MAX_ADDRESS];
+        program[0] = 6; // jmp 0x7FFF
+        program[1] = 0x7FFF;
+        program[0x7FFF] = 2; // push <r0>
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_strict(true);
+
+        assert!(!cpu.execute().expect("The jump is valid"));
+        match cpu.execute() {
+            Err(CPUError::TruncatedInstruction(0x7FFF)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        cpu.set_strict(false);
+        assert!(!cpu.execute().expect("The register is read as the operand"));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let mut cpu = cpu_with_program(&[11, 32768, 5, 0]);
+
+        match cpu.execute() {
+            Err(CPUError::DivisionByZero(0)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_backtrace() {
+        // 0: set r0 7; 3: call 10; 5: halt; 10: push 1; 12: call 20; 14: pop r1; 16: ret; 20: ret
+        let mut program = vec![0; 22];
+        program[..6].copy_from_slice(&[1, 32768, 7, 17, 10, 0]);
+        program[10..17].copy_from_slice(&[2, 1, 17, 20, 3, 32769, 18]);
+        program[20] = 18;
+        let mut cpu = cpu_with_program(&program);
+
+        cpu.execute().ok();
+        cpu.execute().ok();
+        assert_eq!(cpu.backtrace().len(), 1);
+        assert_eq!(cpu.backtrace()[0].call_site, 3);
+        assert_eq!(cpu.backtrace()[0].target, 10);
+        assert_eq!(cpu.backtrace()[0].arguments, [7, 0, 0]);
+
+        cpu.execute().ok(); // push 1
+        cpu.execute().ok(); // call 20
+        assert_eq!(cpu.backtrace().len(), 2);
+        assert_eq!(cpu.backtrace()[1].call_site, 12);
+
+        cpu.execute().ok(); // ret from 20
+        assert_eq!(cpu.backtrace().len(), 1);
+        assert_eq!(cpu.get_current_address(), 14);
+
+        cpu.execute().ok(); // pop r1
+        assert_eq!(cpu.backtrace().len(), 1);
+        cpu.execute().ok(); // ret from 10
+        assert!(cpu.backtrace().is_empty());
+        assert_eq!(cpu.get_current_address(), 5);
+    }
+
+    #[test]
+    fn test_input_output() {
+        // in r0; out r0; in r1; out 33
+        let mut cpu = cpu_with_program(&[20, 32768, 19, 32768, 20, 32769, 19, 33]);
+
+        assert!(cpu.needs_input());
+        match cpu.execute() {
+            Err(CPUError::EmptyInput) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        cpu.feed_input(b"ok");
+        assert!(!cpu.needs_input());
+        assert_eq!(cpu.pending_input(), 2);
+        for _ in 0..4 {
+            cpu.execute().expect("The program must not fail");
+        }
+
+        assert_eq!(cpu.read_register(0), Some(b'o' as u16));
+        assert_eq!(cpu.read_register(1), Some(b'k' as u16));
+        assert_eq!(cpu.pending_input(), 0);
+        assert_eq!(cpu.take_output(), b"o!");
+        assert!(cpu.take_output().is_empty());
+    }
+
     #[test]
     fn test_write_register() {
         let mut cpu = CPU::new(Rc::new(RefCell::new(Memory::default())));
@@ -519,21 +894,21 @@ mod tests {
             assert_eq!(number, 0x10);
         }
     }
-    /*
-        #[test]
-        fn test_stack() {
-            let mut cpu = CPU::new(Rc::new(RefCell::new(Memory::default())));
 
-            assert_eq!(cpu.pop(), None);
+    #[test]
+    fn test_stack() {
+        let mut cpu = CPU::new(Rc::new(RefCell::new(Memory::default())));
+
+        assert_eq!(cpu.pop_stack(), None);
 
-            cpu.push(1);
-            cpu.push(2);
-            cpu.push(3);
+        cpu.push_stack(1);
+        cpu.push_stack(2);
+        cpu.push_stack(3);
+        assert_eq!(cpu.stack(), [1, 2, 3]);
 
-            assert_eq!(cpu.pop(), Some(3));
-            assert_eq!(cpu.pop(), Some(2));
-            assert_eq!(cpu.pop(), Some(1));
-            assert_eq!(cpu.pop(), None);
-        }
-     */
+        assert_eq!(cpu.pop_stack(), Some(3));
+        assert_eq!(cpu.pop_stack(), Some(2));
+        assert_eq!(cpu.pop_stack(), Some(1));
+        assert_eq!(cpu.pop_stack(), None);
+    }
 }
@@ -0,0 +1,821 @@
+//! The command language of the interactive debugger.
+//!
+//! A line is parsed into a [`Command`] with [`str::parse`], the [`Debugger`] executes it against
+//! the [`VirtualMachine`].
+
+use crate::codes::CodeCollector;
+use crate::cpu::MAX_REGISTERS;
+use crate::game::GameState;
+use crate::map::MapRecorder;
+use crate::slots::{RecentOutput, SaveSlots, SlotInfo};
+use crate::terminal::{load_script, DEFAULT_HOST_PREFIX};
+use crate::timeline::{DEFAULT_INTERVAL, DEFAULT_LENGTH};
+use crate::transcript::format_timestamp;
+use crate::vm::{Observer, Stop, VirtualMachine, VirtualMachineError, DEFAULT_CHECKPOINTS};
+use std::cell::{Ref, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// The commands with their arguments and descriptions, it is shown by `help`.
+pub const HELP: &[(&str, &str)] = &[
+    ("help", "shows this help"),
+    ("step [n]", "executes n instructions or up to a breakpoint, 1 by default (alias: s, stepi)"),
+    ("next", "executes an instruction, a call is executed as a whole (alias: n)"),
+    ("finish", "runs until the current function returns"),
+    ("continue", "runs until the program stops or reaches a breakpoint (alias: c, run)"),
+    ("until <addr>", "runs until the program reaches an address greater or equal to addr"),
+    ("break <addr>", "sets a breakpoint (alias: b)"),
+    ("delete [addr]", "removes a breakpoint, all breakpoints without addr (alias: d)"),
+    ("info regs|stack|breaks|timeline", "shows the registers, the stack, the breakpoints or the checkpoints (alias: i)"),
+    ("regs", "shows the registers, same as 'info regs'"),
+    ("x/N <addr>", "shows N memory words starting at addr"),
+    ("set <r0..r7|pc|addr> <value>", "writes a register, the program counter or memory"),
+    ("backtrace", "shows the call stack (alias: bt)"),
+    ("where", "shows the current address"),
+    ("strict on|off", "enables or disables the strict spec-conformance mode"),
+    ("script <file>", "queues the game input from a file or a transcript, then the keyboard is used"),
+    ("save <file>", "saves the state of the VM to a file"),
+    ("load <file>", "restores the state of the VM from a file"),
+    ("rewind <step>", "travels back to the instruction count using the periodic checkpoints"),
+    ("undo [n]", "restores the state from before the last n game commands, 1 by default"),
+    ("slots", "lists the save slots, the most recent first"),
+    ("slot save|load|show|delete <name>", "saves, restores, describes or deletes a named save slot"),
+    ("slot rename <name> <new name>", "renames a save slot"),
+    ("status", "shows the current room, its exits and items, and the inventory"),
+    ("codes", "lists the challenge codes found in the output"),
+    ("map <file>", "writes the rooms visited so far, as JSON for a .json file, as a Graphviz graph otherwise"),
+    ("exit", "exits the debugger (alias: quit, q)"),
+];
+
+// Every command name and alias, used for the suggestions
+const NAMES: &[&str] = &[
+    "help", "step", "s", "stepi", "next", "n", "finish", "continue", "c", "run", "until", "break",
+    "b", "delete", "d", "info", "i", "regs", "x", "set", "backtrace", "bt", "where", "strict", "script", "save", "load", "rewind", "undo", "slots", "slot", "status", "codes", "map",
+    "exit", "quit", "q",
+];
+
+/// A debugger command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Step(usize),
+    Next,
+    Finish,
+    Continue,
+    Until(u16),
+    Break(u16),
+    Delete(Option<u16>),
+    Info(Info),
+    Examine { address: u16, count: u16 },
+    Set(Location, u16),
+    Backtrace,
+    Where,
+    Strict(bool),
+    Script(String),
+    Save(String),
+    Load(String),
+    Rewind(u64),
+    Undo(usize),
+    Slot(Slot),
+    Status,
+    Codes,
+    Map(String),
+    Exit,
+}
+
+/// What `info` shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Info {
+    Registers,
+    Stack,
+    Breakpoints,
+    Timeline,
+}
+
+/// What `slot` does with the save slots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Slot {
+    List,
+    Save(String),
+    Load(String),
+    Show(String),
+    Delete(String),
+    Rename(String, String),
+}
+
+/// What `set` writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Register(u8),
+    ProgramCounter,
+    Memory(u16),
+}
+
+/// Errors reported while parsing a command.
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    /// The line is empty.
+    Empty,
+    /// There is no such command, contains the closest known commands.
+    Unknown { command: String, suggestions: Vec<&'static str> },
+    /// A mandatory argument is absent.
+    MissingArgument { command: &'static str, argument: &'static str },
+    /// The argument couldn't be parsed.
+    InvalidArgument { command: &'static str, argument: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Empty => write!(f, "Empty command, type 'help' to see the commands"),
+            CommandError::Unknown { command, suggestions } if suggestions.is_empty() => {
+                write!(f, "Unknown command '{}', type 'help' to see the commands", command)
+            }
+            CommandError::Unknown { command, suggestions } => {
+                write!(f, "Unknown command '{}', did you mean '{}'?", command, suggestions.join("', '"))
+            }
+            CommandError::MissingArgument { command, argument } => {
+                write!(f, "'{}' expects {}", command, argument)
+            }
+            CommandError::InvalidArgument { command, argument } => {
+                write!(f, "'{}' cannot use '{}'", command, argument)
+            }
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = CommandError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or(CommandError::Empty)?;
+        let args: Vec<&str> = words.collect();
+
+        if let Some(count) = name.strip_prefix("x/") {
+            let count = count.parse::<u16>()
+                .map_err(|_| CommandError::InvalidArgument { command: "x", argument: count.to_string() })?;
+            let address = parse_address("x", args.first().copied())?;
+            return Ok(Command::Examine { address, count });
+        }
+
+        let command = match name {
+            "help" | "h" | "?" => Command::Help,
+            "step" | "s" | "stepi" => match args.first() {
+                Some(count) => Command::Step(count.parse::<usize>()
+                    .map_err(|_| CommandError::InvalidArgument { command: "step", argument: count.to_string() })?),
+                None => Command::Step(1),
+            },
+            "next" | "n" => Command::Next,
+            "finish" => Command::Finish,
+            "continue" | "c" | "run" => Command::Continue,
+            "until" => Command::Until(parse_address("until", args.first().copied())?),
+            "break" | "b" => Command::Break(parse_address("break", args.first().copied())?),
+            "delete" | "d" => match args.first() {
+                Some(_) => Command::Delete(Some(parse_address("delete", args.first().copied())?)),
+                None => Command::Delete(None),
+            },
+            "regs" => Command::Info(Info::Registers),
+            "info" | "i" => match args.first() {
+                Some(&"regs") | Some(&"registers") | Some(&"r") => Command::Info(Info::Registers),
+                Some(&"stack") | Some(&"s") => Command::Info(Info::Stack),
+                Some(&"breaks") | Some(&"breakpoints") | Some(&"b") => Command::Info(Info::Breakpoints),
+                Some(&"timeline") | Some(&"t") => Command::Info(Info::Timeline),
+                Some(what) => return Err(CommandError::InvalidArgument { command: "info", argument: what.to_string() }),
+                None => return Err(CommandError::MissingArgument { command: "info", argument: "regs, stack, breaks or timeline" }),
+            },
+            "x" => Command::Examine { address: parse_address("x", args.first().copied())?, count: 1 },
+            "set" => {
+                let location = match args.first() {
+                    Some(&"pc") => Location::ProgramCounter,
+                    Some(location) => match parse_register(location) {
+                        Some(register) => Location::Register(register),
+                        None => Location::Memory(parse_address("set", Some(location))?),
+                    },
+                    None => return Err(CommandError::MissingArgument { command: "set", argument: "a register, pc or an address" }),
+                };
+                let value = match args.get(1) {
+                    Some(value) => parse_number(value)
+                        .ok_or_else(|| CommandError::InvalidArgument { command: "set", argument: value.to_string() })?,
+                    None => return Err(CommandError::MissingArgument { command: "set", argument: "a value" }),
+                };
+                Command::Set(location, value)
+            }
+            "backtrace" | "bt" => Command::Backtrace,
+            "where" => Command::Where,
+            "strict" => match args.first() {
+                Some(&"on") => Command::Strict(true),
+                Some(&"off") => Command::Strict(false),
+                Some(what) => return Err(CommandError::InvalidArgument { command: "strict", argument: what.to_string() }),
+                None => return Err(CommandError::MissingArgument { command: "strict", argument: "on or off" }),
+            },
+            "script" => Command::Script(parse_path("script", &args)?),
+            "save" => Command::Save(parse_path("save", &args)?),
+            "load" => Command::Load(parse_path("load", &args)?),
+            "rewind" => match args.first() {
+                Some(step) => Command::Rewind(step.parse::<u64>()
+                    .map_err(|_| CommandError::InvalidArgument { command: "rewind", argument: step.to_string() })?),
+                None => return Err(CommandError::MissingArgument { command: "rewind", argument: "an instruction count" }),
+            },
+            "undo" => match args.first() {
+                Some(count) => Command::Undo(count.parse::<usize>()
+                    .map_err(|_| CommandError::InvalidArgument { command: "undo", argument: count.to_string() })?),
+                None => Command::Undo(1),
+            },
+            "slots" => Command::Slot(Slot::List),
+            "slot" => {
+                let name = |index: usize| args.get(index).map(|name| name.to_string())
+                    .ok_or(CommandError::MissingArgument { command: "slot", argument: "a slot name" });
+                match args.first() {
+                    None | Some(&"list") => Command::Slot(Slot::List),
+                    Some(&"save") => Command::Slot(Slot::Save(name(1)?)),
+                    Some(&"load") => Command::Slot(Slot::Load(name(1)?)),
+                    Some(&"show") => Command::Slot(Slot::Show(name(1)?)),
+                    Some(&"delete") => Command::Slot(Slot::Delete(name(1)?)),
+                    Some(&"rename") => Command::Slot(Slot::Rename(name(1)?, name(2)?)),
+                    Some(what) => return Err(CommandError::InvalidArgument { command: "slot", argument: what.to_string() }),
+                }
+            }
+            "status" => Command::Status,
+            "codes" => Command::Codes,
+            "map" => Command::Map(parse_path("map", &args)?),
+            "exit" | "quit" | "q" => Command::Exit,
+            _ => return Err(CommandError::Unknown {
+                command: name.to_string(),
+                suggestions: suggest(name),
+            }),
+        };
+
+        Ok(command)
+    }
+}
+
+/// Parses a decimal or a hexadecimal (`0x` prefix) number.
+pub fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u16>().ok(),
+    }
+}
+
+fn parse_address(command: &'static str, text: Option<&str>) -> Result<u16, CommandError> {
+    let text = text.ok_or(CommandError::MissingArgument { command, argument: "an address" })?;
+    parse_number(text).ok_or_else(|| CommandError::InvalidArgument { command, argument: text.to_string() })
+}
+
+fn parse_path(command: &'static str, args: &[&str]) -> Result<String, CommandError> {
+    match args {
+        [] => Err(CommandError::MissingArgument { command, argument: "a file name" }),
+        args => Ok(args.join(" ")),
+    }
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    text.strip_prefix('r')
+        .and_then(|number| number.parse::<u8>().ok())
+        .filter(|&number| (number as usize) < MAX_REGISTERS)
+}
+
+// Returns the commands that start with `name` or are a couple of edits away from it
+fn suggest(name: &str) -> Vec<&'static str> {
+    let max_distance = if name.len() > 4 { 2 } else { 1 };
+    NAMES.iter()
+        .filter(|known| known.len() > 1)
+        .filter(|known| known.starts_with(name) || edit_distance(name, known) <= max_distance)
+        .copied()
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Executes the debugger commands against a [`VirtualMachine`].
+pub struct Debugger {
+    pub vm: VirtualMachine,
+    last_command: Option<Command>,
+    slots: SaveSlots,
+    recent: Rc<RefCell<RecentOutput>>,
+    codes: Rc<RefCell<CodeCollector>>,
+    map: Rc<RefCell<MapRecorder>>,
+    state: Rc<RefCell<GameState>>,
+}
+
+impl Debugger {
+    /// Creates the debugger, the lines typed after [`DEFAULT_HOST_PREFIX`] during the game are
+    /// executed as debugger commands, the last [`DEFAULT_CHECKPOINTS`] game commands can be undone and
+    /// the timeline keeps a checkpoint every [`DEFAULT_INTERVAL`] instructions for `rewind`.
+    pub fn new(mut vm: VirtualMachine) -> Debugger {
+        vm.set_host_prefix(Some(DEFAULT_HOST_PREFIX));
+        vm.set_checkpoint_limit(DEFAULT_CHECKPOINTS);
+        vm.set_timeline(DEFAULT_INTERVAL, DEFAULT_LENGTH);
+        let recent = Rc::new(RefCell::new(RecentOutput::default()));
+        vm.add_observer(recent.clone());
+        let codes = Rc::new(RefCell::new(CodeCollector::default()));
+        vm.add_observer(codes.clone());
+        let map = Rc::new(RefCell::new(MapRecorder::default()));
+        vm.add_observer(map.clone());
+        let state = Rc::new(RefCell::new(GameState::default()));
+        vm.add_observer(state.clone());
+        Debugger {
+            vm,
+            last_command: None,
+            slots: SaveSlots::default(),
+            recent,
+            codes,
+            map,
+            state,
+        }
+    }
+
+    /// Returns the state of the game tracked from the output.
+    pub fn state(&self) -> Ref<'_, GameState> {
+        self.state.borrow()
+    }
+
+    /// Replaces the collector of the codes, e.g. with one that keeps them in a file.
+    ///
+    /// The collector observes the VM, unless it does already.
+    pub fn set_codes(&mut self, codes: Rc<RefCell<CodeCollector>>) {
+        replace_observer(&mut self.vm, &self.codes, &codes);
+        self.codes = codes;
+    }
+
+    /// Replaces the recorder of the map, e.g. with one shared with the caller.
+    ///
+    /// The recorder observes the VM, unless it does already.
+    pub fn set_map(&mut self, map: Rc<RefCell<MapRecorder>>) {
+        replace_observer(&mut self.vm, &self.map, &map);
+        self.map = map;
+    }
+
+    /// Replaces the save slots, the `saves` directory by default.
+    pub fn set_slots(&mut self, slots: SaveSlots) {
+        self.slots = slots;
+    }
+
+    /// Parses and executes a line, an empty line repeats the last command.
+    ///
+    /// Returns `true` when the debugger should exit: the program has stopped or `exit` was typed.
+    pub fn execute_line(&mut self, line: &str) -> Result<bool, VirtualMachineError> {
+        let command = match (line.parse::<Command>(), &self.last_command) {
+            (Ok(command), _) => command,
+            (Err(CommandError::Empty), Some(last_command)) => last_command.clone(),
+            (Err(err), _) => {
+                eprintln!("{}", err);
+                return Ok(false);
+            }
+        };
+
+        let result = self.execute(&command);
+        self.last_command = Some(command);
+        result
+    }
+
+    /// Executes a command, returns `true` when the debugger should exit.
+    pub fn execute(&mut self, command: &Command) -> Result<bool, VirtualMachineError> {
+        let vm = &mut self.vm;
+
+        match command {
+            Command::Help => {
+                println!("--- Commands ---");
+                HELP.iter().for_each(|(command, description)| println!("{:<30} {}", command, description));
+            }
+            Command::Step(count) => return self.play(|vm| vm.step(*count)),
+            Command::Next => return self.play(VirtualMachine::step_over),
+            Command::Finish => {
+                if vm.cpu.backtrace().is_empty() {
+                    eprintln!("Not inside of a function, use 'continue' instead");
+                } else {
+                    return self.play(VirtualMachine::finish);
+                }
+            }
+            Command::Continue => return self.play(VirtualMachine::resume),
+            Command::Until(address) => return self.play(|vm| vm.run_until(*address)),
+            Command::Break(address) => {
+                if !vm.add_breakpoint(*address) {
+                    eprintln!("The breakpoint {:#06X} is already set", address);
+                }
+            }
+            Command::Delete(Some(address)) => {
+                if !vm.remove_breakpoint(*address) {
+                    eprintln!("There is no breakpoint at {:#06X}", address);
+                }
+            }
+            Command::Delete(None) => vm.clear_breakpoints(),
+            Command::Info(Info::Registers) => vm.dump_registry(),
+            Command::Info(Info::Stack) => {
+                println!("--- Stack ---");
+                vm.cpu.stack().iter().rev().for_each(|value| println!("{:#06X}", value));
+            }
+            Command::Info(Info::Breakpoints) => {
+                println!("--- Breakpoints ---");
+                vm.breakpoints().for_each(|address| println!("{0:#06X} / {0}", address));
+            }
+            Command::Info(Info::Timeline) => {
+                println!("--- Checkpoints ---");
+                vm.timeline().steps().for_each(|step| println!("{}", step));
+                println!("Current step: {}", vm.steps());
+            }
+            Command::Examine { address, count } => {
+                for row in (0..*count).step_by(8) {
+                    let start = address.saturating_add(row);
+                    let words: Vec<String> = (start..start.saturating_add((count - row).min(8)))
+                        .map(|address| match vm.cpu.get_value_from_address(address) {
+                            Ok(value) => format!("{:#06X}", value),
+                            Err(_) => String::from("------"),
+                        })
+                        .collect();
+                    println!("{:#06X}: {}", start, words.join(" "));
+                }
+            }
+            Command::Set(Location::Register(register), value) => {
+                vm.cpu.write_register(*register, *value).map_err(VirtualMachineError::CPUFault)?;
+                vm.clear_timeline();
+            }
+            Command::Set(Location::ProgramCounter, value) => {
+                vm.cpu.set_current_address(*value);
+                vm.clear_timeline();
+            }
+            Command::Set(Location::Memory(address), value) => {
+                if vm.cpu.set_value_in_address(*address, *value).is_err() {
+                    eprintln!("Couldn't write to {:#06X}", address);
+                }
+                vm.clear_timeline();
+            }
+            Command::Backtrace => vm.dump_backtrace(),
+            Command::Where => println!("{0:#6} / {0:#06X}", vm.get_current_address()),
+            Command::Strict(strict) => vm.set_strict(*strict),
+            Command::Script(path) => match load_script(path) {
+                Ok(script) => {
+                    println!("Queued {} lines from '{}'", script.len(), path);
+                    vm.add_script(script);
+                }
+                Err(err) => eprintln!("Couldn't read '{}': {}", path, err),
+            },
+            Command::Save(path) => match vm.save_snapshot(path) {
+                Ok(_) => println!("Saved to '{}' at step {}", path, vm.steps()),
+                Err(err) => eprintln!("Couldn't save to '{}': {:?}", path, err),
+            },
+            Command::Load(path) => match vm.load_snapshot(path) {
+                Ok(_) => {
+                    println!("Loaded '{}', step {} at {:#06X}", path, vm.steps(), vm.get_current_address());
+                    self.forget_game();
+                }
+                Err(err) => eprintln!("Couldn't load '{}': {:?}", path, err),
+            },
+            Command::Rewind(step) => match vm.rewind(*step) {
+                Ok(checkpoint) => {
+                    println!("Rewound to step {} from the checkpoint at step {}, {:#06X}",
+                             vm.steps(), checkpoint, vm.get_current_address());
+                    self.forget_game();
+                }
+                Err(VirtualMachineError::OutOfTimeline(_)) => eprintln!(
+                    "The step {} is not in the timeline, the checkpoints start at step {}, the current step is {}",
+                    step, vm.timeline().steps().next().map_or(String::from("-"), |first| first.to_string()), vm.steps()),
+                Err(err) => eprintln!("Couldn't rewind: {:?}", err),
+            },
+            Command::Undo(count) => match vm.undo(*count) {
+                Ok(0) => eprintln!("There is nothing to undo"),
+                Ok(undone) => {
+                    println!("Undone {} of {} commands, back at step {}, the game waits for the command",
+                             undone, count, vm.steps());
+                    self.forget_game();
+                }
+                Err(err) => eprintln!("Couldn't undo: {:?}", err),
+            },
+            Command::Slot(slot) => self.execute_slot(slot),
+            Command::Status => match self.state.borrow().status_line() {
+                Some(status) => println!("{}", status),
+                None => eprintln!("No room is described yet"),
+            },
+            Command::Codes => {
+                println!("--- Codes ---");
+                for code in self.codes.borrow().codes() {
+                    println!("{} {:>12} steps  {}", code.code, code.step, code.context);
+                }
+            }
+            Command::Map(path) => {
+                let mut map = self.map.borrow_mut();
+                match map.map().export(path) {
+                    Ok(_) => println!("Written {} rooms to '{}'", map.map().rooms().len(), path),
+                    Err(err) => eprintln!("Couldn't write the map to '{}': {}", path, err),
+                }
+            }
+            Command::Exit => return Ok(true),
+        }
+
+        Ok(false)
+    }
+
+    fn execute_slot(&mut self, slot: &Slot) {
+        match slot {
+            Slot::List => match self.slots.list() {
+                Ok(slots) => {
+                    println!("--- Save slots in '{}' ---", self.slots.directory().display());
+                    for info in slots {
+                        println!("{:<20} {} {:>12} steps  {}", info.name, format_timestamp(info.time), info.steps,
+                                 info.room.as_deref().unwrap_or("-"));
+                    }
+                }
+                Err(err) => eprintln!("Couldn't list the save slots: {:?}", err),
+            },
+            Slot::Save(name) => match self.slots.save(name, &self.vm, &self.recent.borrow()) {
+                Ok(info) => println!("Saved the slot '{}' at step {}", name, info.steps),
+                Err(err) => eprintln!("Couldn't save the slot '{}': {:?}", name, err),
+            },
+            Slot::Load(name) => match self.slots.load(name, &mut self.vm) {
+                Ok(info) => {
+                    self.recent.borrow_mut().restore(&info);
+                    self.forget_game();
+                    print_slot(&info);
+                }
+                Err(err) => eprintln!("Couldn't load the slot '{}': {:?}", name, err),
+            },
+            Slot::Show(name) => match self.slots.info(name) {
+                Ok(info) => print_slot(&info),
+                Err(err) => eprintln!("Couldn't read the slot '{}': {:?}", name, err),
+            },
+            Slot::Delete(name) => match self.slots.delete(name) {
+                Ok(_) => println!("Deleted the slot '{}'", name),
+                Err(err) => eprintln!("Couldn't delete the slot '{}': {:?}", name, err),
+            },
+            Slot::Rename(from, to) => match self.slots.rename(from, to) {
+                Ok(_) => println!("Renamed the slot '{}' to '{}'", from, to),
+                Err(err) => eprintln!("Couldn't rename the slot '{}': {:?}", from, err),
+            },
+        }
+    }
+
+    // The state of the game seen in the output is stale after the VM was restored, it is known again
+    // when the game describes a room or lists the inventory
+    fn forget_game(&mut self) {
+        self.state.borrow_mut().reset();
+        self.map.borrow_mut().reset();
+    }
+
+    // Runs the program, the host commands typed during the game are executed and the game resumes
+    fn play<F>(&mut self, run: F) -> Result<bool, VirtualMachineError>
+        where F: FnOnce(&mut VirtualMachine) -> Result<Option<Stop>, VirtualMachineError> {
+        let mut result = run(&mut self.vm);
+        while let Ok(Some(Stop::HostCommand(line))) = &result {
+            if self.execute_host_command(line)? {
+                return Ok(true);
+            }
+            result = self.vm.resume();
+        }
+
+        report(result, self.vm.checkpoints() > 0)
+    }
+
+    // Executes a line typed after the host prefix, returns `true` when the debugger should exit
+    fn execute_host_command(&mut self, line: &str) -> Result<bool, VirtualMachineError> {
+        match line.parse::<Command>() {
+            Ok(Command::Step(_)) | Ok(Command::Next) | Ok(Command::Finish) | Ok(Command::Continue) | Ok(Command::Until(_)) => {
+                eprintln!("'{}' cannot be used during the game, press Ctrl-] and Enter to pause it", line);
+                Ok(false)
+            }
+            Ok(command) => self.execute(&command),
+            Err(err) => {
+                eprintln!("{}", err);
+                Ok(false)
+            }
+        }
+    }
+}
+
+// Replaces an observer of the VM, the new one observes it once
+fn replace_observer<T: Observer + 'static>(vm: &mut VirtualMachine, old: &Rc<RefCell<T>>, new: &Rc<RefCell<T>>) {
+    let old: Rc<RefCell<dyn Observer>> = old.clone();
+    let new: Rc<RefCell<dyn Observer>> = new.clone();
+    vm.remove_observer(&old);
+    vm.remove_observer(&new);
+    vm.add_observer(new);
+}
+
+fn print_slot(info: &SlotInfo) {
+    println!("--- {} ---", info.name);
+    println!("Saved at {}, step {}, room: {}", format_timestamp(info.time), info.steps,
+             info.room.as_deref().unwrap_or("-"));
+    info.tail.iter().for_each(|line| println!("  {}", line));
+}
+
+// Tells the user why the program has stopped, returns `true` if it cannot continue; a halted game
+// can continue while there are commands to undo, e.g. after walking into a grue, and a faulted
+// program can be inspected
+fn report(result: Result<Option<Stop>, VirtualMachineError>, can_undo: bool) -> Result<bool, VirtualMachineError> {
+    let stop = match result {
+        Err(VirtualMachineError::CPUFault(err)) => {
+            eprintln!("\nThe program has faulted: {:?}", err);
+            return Ok(false);
+        }
+        result => result?,
+    };
+    match stop {
+        None | Some(Stop::HostCommand(_)) => Ok(false),
+        Some(Stop::Breakpoint(address)) => {
+            println!("\nBreakpoint at {:#06X}", address);
+            Ok(false)
+        }
+        Some(Stop::Interrupted) => {
+            println!("Paused, type 'continue' to return to the game");
+            Ok(false)
+        }
+        Some(Stop::EndOfInput) => {
+            println!("\nThe game input has ended");
+            Ok(false)
+        }
+        Some(Stop::StepLimit) => {
+            println!("\nThe step limit is reached");
+            Ok(false)
+        }
+        Some(Stop::Halted) if can_undo => {
+            println!("\nThe program has halted, type 'undo' to return to the last command or 'exit'");
+            Ok(false)
+        }
+        Some(Stop::Halted) => Ok(true),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::LineReader;
+    use std::io;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("1234"), Some(1234));
+        assert_eq!(parse_number("0x1234"), Some(0x1234));
+        assert_eq!(parse_number("0X7fff"), Some(0x7FFF));
+        assert_eq!(parse_number("0xZ"), None);
+        assert_eq!(parse_number("70000"), None);
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("help".parse(), Ok(Command::Help));
+        assert_eq!("step".parse(), Ok(Command::Step(1)));
+        assert_eq!("s 10".parse(), Ok(Command::Step(10)));
+        assert_eq!("continue".parse(), Ok(Command::Continue));
+        assert_eq!("break 0x1234".parse(), Ok(Command::Break(0x1234)));
+        assert_eq!("delete".parse(), Ok(Command::Delete(None)));
+        assert_eq!("d 12".parse(), Ok(Command::Delete(Some(12))));
+        assert_eq!("info regs".parse(), Ok(Command::Info(Info::Registers)));
+        assert_eq!("info stack".parse(), Ok(Command::Info(Info::Stack)));
+        assert_eq!("info breaks".parse(), Ok(Command::Info(Info::Breakpoints)));
+        assert_eq!("x/16 0x100".parse(), Ok(Command::Examine { address: 0x100, count: 16 }));
+        assert_eq!("set r7 25734".parse(), Ok(Command::Set(Location::Register(7), 25734)));
+        assert_eq!("set pc 0x10".parse(), Ok(Command::Set(Location::ProgramCounter, 0x10)));
+        assert_eq!("set 0x1000 21".parse(), Ok(Command::Set(Location::Memory(0x1000), 21)));
+        assert_eq!("  bt  ".parse(), Ok(Command::Backtrace));
+        assert_eq!("save the vault.snap".parse(), Ok(Command::Save(String::from("the vault.snap"))));
+        assert_eq!("load vault.snap".parse(), Ok(Command::Load(String::from("vault.snap"))));
+        assert_eq!("script walkthrough.txt".parse(), Ok(Command::Script(String::from("walkthrough.txt"))));
+        assert_eq!("rewind 123456".parse(), Ok(Command::Rewind(123456)));
+        assert_eq!("info timeline".parse(), Ok(Command::Info(Info::Timeline)));
+        assert_eq!("undo".parse(), Ok(Command::Undo(1)));
+        assert_eq!("undo 3".parse(), Ok(Command::Undo(3)));
+        assert_eq!("slots".parse(), Ok(Command::Slot(Slot::List)));
+        assert_eq!("codes".parse(), Ok(Command::Codes));
+        assert_eq!("status".parse(), Ok(Command::Status));
+        assert_eq!("map maze.dot".parse(), Ok(Command::Map(String::from("maze.dot"))));
+        assert_eq!("slot save vault".parse(), Ok(Command::Slot(Slot::Save(String::from("vault")))));
+        assert_eq!("slot rename a b".parse(), Ok(Command::Slot(Slot::Rename(String::from("a"), String::from("b")))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("".parse::<Command>(), Err(CommandError::Empty));
+        assert_eq!("info".parse::<Command>(), Err(CommandError::MissingArgument {
+            command: "info",
+            argument: "regs, stack, breaks or timeline",
+        }));
+        assert_eq!("break here".parse::<Command>(), Err(CommandError::InvalidArgument {
+            command: "break",
+            argument: String::from("here"),
+        }));
+        assert_eq!("set r8 1".parse::<Command>(), Err(CommandError::InvalidArgument {
+            command: "set",
+            argument: String::from("r8"),
+        }));
+        assert_eq!("contnue".parse::<Command>(), Err(CommandError::Unknown {
+            command: String::from("contnue"),
+            suggestions: vec!["continue"],
+        }));
+        assert_eq!("ste".parse::<Command>(), Err(CommandError::Unknown {
+            command: String::from("ste"),
+            suggestions: vec!["step", "stepi"],
+        }));
+        assert_eq!("slot rename a".parse::<Command>(), Err(CommandError::MissingArgument {
+            command: "slot",
+            argument: "a slot name",
+        }));
+        assert_eq!("fooooo".parse::<Command>(), Err(CommandError::Unknown {
+            command: String::from("fooooo"),
+            suggestions: vec![],
+        }));
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![21, 21, 21, 0]).expect("The binary should load without errors");
+        let mut debugger = Debugger::new(vm);
+
+        assert_eq!(debugger.execute_line("break 2").ok(), Some(false));
+        assert_eq!(debugger.execute_line("set r3 0x10").ok(), Some(false));
+        assert_eq!(debugger.vm.cpu.read_register(3), Some(0x10));
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert_eq!(debugger.vm.get_current_address(), 2);
+        assert_eq!(debugger.execute_line("typo").ok(), Some(false));
+        assert_eq!(debugger.vm.get_current_address(), 2);
+        assert_eq!(debugger.execute_line("step").ok(), Some(false));
+        assert_eq!(debugger.execute_line("").ok(), Some(true));
+    }
+
+    #[test]
+    fn test_fault() {
+        // 0: noop; 1: unknown
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![21, 22]).expect("The binary should load without errors");
+        let mut debugger = Debugger::new(vm);
+
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert_eq!(debugger.vm.get_current_address(), 1);
+        assert_eq!(debugger.execute_line("where").ok(), Some(false));
+    }
+
+    #[test]
+    fn test_host_commands() {
+        // 0: in r0; 2: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 6, 0]).expect("The binary should load without errors");
+        vm.add_script(vec![String::from("!break 0x1234"), String::from("!set r1 7"), String::from("!step"),
+                           String::from("!typo"), String::from("a")]);
+        vm.set_input(LineReader::new(io::empty()));
+        let mut debugger = Debugger::new(vm);
+
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert_eq!(debugger.vm.breakpoints().collect::<Vec<_>>(), [0x1234]);
+        assert_eq!(debugger.vm.cpu.read_register(1), Some(7));
+        assert_eq!(debugger.vm.cpu.read_register(0), Some(b'\n' as u16));
+
+        debugger.vm.add_script(vec![String::from("!exit")]);
+        assert_eq!(debugger.execute_line("continue").ok(), Some(true));
+    }
+
+    #[test]
+    fn test_undo_after_halt() {
+        // 0: in r0; 2: eq r1 r0 'q'; 6: jt r1 13; 9: out r0; 11: jmp 0; 13: halt
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0])
+            .expect("The binary should load without errors");
+        vm.add_script(vec![String::from("a"), String::from("q")]);
+        vm.set_input(LineReader::new(io::empty()));
+        let mut debugger = Debugger::new(vm);
+
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert_eq!(debugger.execute_line("undo").ok(), Some(false));
+        assert_eq!(debugger.vm.get_current_address(), 0);
+        debugger.vm.add_script(vec![String::from("b")]);
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert_eq!(debugger.vm.cpu.read_register(0), Some(b'\n' as u16));
+    }
+
+    #[test]
+    fn test_state_after_undo() {
+        // out <room>; in r0; jmp <in>
+        let mut program: Vec<u16> = "== Foothills ==\nThe Foothills.\n\nThere is 1 exit:\n- south\n\nWhat do I do?\n"
+            .bytes().flat_map(|c| vec![19, c as u16]).collect();
+        let read = program.len() as u16;
+        program.extend_from_slice(&[20, 32768, 6, read]);
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| program).expect("The binary should load without errors");
+        vm.set_output(crate::terminal::Capture::default());
+        vm.set_input(LineReader::new(io::empty()));
+        vm.add_script(vec![String::from("inv")]);
+        let mut debugger = Debugger::new(vm);
+
+        assert_eq!(debugger.execute_line("continue").ok(), Some(false));
+        assert!(debugger.state().room().is_some());
+        assert_eq!(debugger.execute_line("undo").ok(), Some(false));
+        assert!(debugger.state().room().is_none());
+        assert_eq!(debugger.state().status_line(), None);
+    }
+}
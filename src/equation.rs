@@ -0,0 +1,303 @@
+//! The equations of the monument puzzles, e.g. `_ + _ * _^2 + _^3 - _ = 399`.
+//!
+//! Every `_` is a slot for an item value, the operators are `+ - * ^` with the usual precedence,
+//! `^` is right-associative, and the parentheses group.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Errors reported while parsing an [`Equation`].
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum EquationError {
+    /// The text has no `=` or more than one.
+    NotAnEquation,
+    /// The character cannot appear in an equation.
+    UnknownCharacter(char),
+    /// An expression ends too early, e.g. `_ + = 3`.
+    UnexpectedEnd,
+    /// The token cannot appear at this place.
+    UnexpectedToken(String),
+    /// There are no slots to fill.
+    NoSlots,
+}
+
+impl fmt::Display for EquationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EquationError::NotAnEquation => write!(f, "The equation must have exactly one '='"),
+            EquationError::UnknownCharacter(c) => write!(f, "Unknown character '{}'", c),
+            EquationError::UnexpectedEnd => write!(f, "The expression is incomplete"),
+            EquationError::UnexpectedToken(token) => write!(f, "Unexpected '{}'", token),
+            EquationError::NoSlots => write!(f, "The equation has no '_' to fill"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Slot,
+    Number(i64),
+    Operator(char),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Slot => write!(f, "_"),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Operator(operator) => write!(f, "{}", operator),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    // The index of the slot, from left to right
+    Slot(usize),
+    Number(i64),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    // `None` when the value overflows or the exponent is negative
+    fn evaluate(&self, values: &[i64]) -> Option<i64> {
+        match self {
+            Expression::Slot(index) => values.get(*index).copied(),
+            Expression::Number(number) => Some(*number),
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(values)?, right.evaluate(values)?);
+                match operator {
+                    '+' => left.checked_add(right),
+                    '-' => left.checked_sub(right),
+                    '*' => left.checked_mul(right),
+                    '^' => u32::try_from(right).ok().and_then(|exponent| left.checked_pow(exponent)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// An equation with slots, parsed with [`str::parse`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equation {
+    left: Expression,
+    right: Expression,
+    slots: usize,
+}
+
+impl Equation {
+    /// Returns the number of `_` slots.
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// Checks the equation with the values of the slots, from left to right.
+    pub fn is_satisfied(&self, values: &[i64]) -> bool {
+        values.len() == self.slots
+            && matches!((self.left.evaluate(values), self.right.evaluate(values)), (Some(left), Some(right)) if left == right)
+    }
+
+    /// Finds every assignment of the distinct items to the slots that satisfies the equation,
+    /// the items are returned in the order of the slots.
+    pub fn solve<'a, T>(&self, items: &'a [(T, i64)]) -> Vec<Vec<&'a (T, i64)>> {
+        let mut solutions = Vec::new();
+        let mut chosen = Vec::with_capacity(self.slots);
+        let mut used = vec![false; items.len()];
+        self.search(items, &mut chosen, &mut used, &mut solutions);
+
+        solutions
+    }
+
+    fn search<'a, T>(&self, items: &'a [(T, i64)], chosen: &mut Vec<usize>, used: &mut [bool],
+                     solutions: &mut Vec<Vec<&'a (T, i64)>>) {
+        if chosen.len() == self.slots {
+            let values: Vec<i64> = chosen.iter().map(|&index| items[index].1).collect();
+            if self.is_satisfied(&values) {
+                solutions.push(chosen.iter().map(|&index| &items[index]).collect());
+            }
+            return;
+        }
+
+        for index in 0..items.len() {
+            if !used[index] {
+                used[index] = true;
+                chosen.push(index);
+                self.search(items, chosen, used, solutions);
+                chosen.pop();
+                used[index] = false;
+            }
+        }
+    }
+}
+
+impl FromStr for Equation {
+    type Err = EquationError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (left, right) = match text.split('=').collect::<Vec<&str>>()[..] {
+            [left, right] => (left, right),
+            _ => return Err(EquationError::NotAnEquation),
+        };
+
+        let mut parser = Parser { tokens: tokenize(left)?, position: 0, slots: 0 };
+        let left = parser.parse_all()?;
+        let slots = parser.slots;
+        let mut parser = Parser { tokens: tokenize(right)?, position: 0, slots };
+        let right = parser.parse_all()?;
+        if parser.slots == 0 {
+            return Err(EquationError::NoSlots);
+        }
+
+        Ok(Equation { left, right, slots: parser.slots })
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, EquationError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '_' => tokens.push(Token::Slot),
+            '+' | '-' | '*' | '^' => tokens.push(Token::Operator(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '0'..='9' => {
+                let mut number = c.to_digit(10).unwrap_or_default() as i64;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    number = number.saturating_mul(10).saturating_add(digit as i64);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_whitespace() => {}
+            c => return Err(EquationError::UnknownCharacter(c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// A recursive descent parser: sum := product (('+' | '-') product)*,
+// product := power ('*' power)*, power := atom ('^' power)?, atom := '_' | number | '(' sum ')'
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    slots: usize,
+}
+
+impl Parser {
+    fn parse_all(&mut self) -> Result<Expression, EquationError> {
+        let expression = self.parse_sum()?;
+        match self.tokens.get(self.position) {
+            Some(token) => Err(EquationError::UnexpectedToken(token.to_string())),
+            None => Ok(expression),
+        }
+    }
+
+    fn next_operator(&mut self, operators: &str) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(*operator) => {
+                self.position += 1;
+                Some(*operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expression, EquationError> {
+        let mut expression = self.parse_product()?;
+        while let Some(operator) = self.next_operator("+-") {
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.parse_product()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_product(&mut self) -> Result<Expression, EquationError> {
+        let mut expression = self.parse_power()?;
+        while let Some(operator) = self.next_operator("*") {
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.parse_power()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_power(&mut self) -> Result<Expression, EquationError> {
+        let base = self.parse_atom()?;
+        match self.next_operator("^") {
+            Some(operator) => Ok(Expression::Binary(operator, Box::new(base), Box::new(self.parse_power()?))),
+            None => Ok(base),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, EquationError> {
+        let token = *self.tokens.get(self.position).ok_or(EquationError::UnexpectedEnd)?;
+        self.position += 1;
+        match token {
+            Token::Slot => {
+                self.slots += 1;
+                Ok(Expression::Slot(self.slots - 1))
+            }
+            Token::Number(number) => Ok(Expression::Number(number)),
+            Token::Open => {
+                let expression = self.parse_sum()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(expression)
+                    }
+                    Some(token) => Err(EquationError::UnexpectedToken(token.to_string())),
+                    None => Err(EquationError::UnexpectedEnd),
+                }
+            }
+            token => Err(EquationError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let equation: Equation = "_ + _ * _^2 + _^3 - _ = 399".parse().expect("The equation is valid");
+        assert_eq!(equation.slots(), 5);
+        assert!(equation.is_satisfied(&[9, 2, 5, 7, 3]));
+        assert!(!equation.is_satisfied(&[2, 3, 5, 7, 9]));
+
+        let equation: Equation = "2 ^ 3 ^ _ = (_ - 1) * 256".parse().expect("The equation is valid");
+        assert!(equation.is_satisfied(&[2, 3]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("_ + 1".parse::<Equation>(), Err(EquationError::NotAnEquation));
+        assert_eq!("_ + = 1".parse::<Equation>(), Err(EquationError::UnexpectedEnd));
+        assert_eq!("_ _ = 1".parse::<Equation>(), Err(EquationError::UnexpectedToken(String::from("_"))));
+        assert_eq!("_ / 2 = 1".parse::<Equation>(), Err(EquationError::UnknownCharacter('/')));
+        assert_eq!("(_ + 2 = 1".parse::<Equation>(), Err(EquationError::UnexpectedEnd));
+        assert_eq!("1 + 2 = 3".parse::<Equation>(), Err(EquationError::NoSlots));
+    }
+
+    #[test]
+    fn test_solve() {
+        let equation: Equation = "_ + _ * _^2 + _^3 - _ = 399".parse().expect("The equation is valid");
+        let coins = [("red", 2), ("corroded", 3), ("shiny", 5), ("concave", 7), ("blue", 9), ("extra", 4)];
+
+        let solutions = equation.solve(&coins);
+        assert_eq!(solutions.len(), 1);
+        let names: Vec<&str> = solutions[0].iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["blue", "red", "shiny", "concave", "corroded"]);
+
+        let equation: Equation = "_ + _ = 5".parse().expect("The equation is valid");
+        assert_eq!(equation.solve(&coins).len(), 2);
+    }
+}
@@ -0,0 +1,107 @@
+/* Explorer of the text adventure:
+Mapping the rooms by hand, especially the twisty maze, is the most tedious part of the challenge.
+The explorer plays the game breadth-first, see `synacor_vm::map::Explorer`: it takes every listed
+exit of every room, reads the room it gets to and restores the snapshot of the previous room to try
+the other exits. The rooms are known by the memory word with the current room, the rooms of the
+maze look alike.
+
+The game is started from the binary, the script is played first (e.g. to get to the interesting
+part), or from a snapshot. A move that ends the game or doesn't describe a room is reported.
+
+The exits that need an item, e.g. the lantern in the dark passages, depend on the state of the
+game when the explorer starts: take the items first with the script.
+ */
+
+use std::env;
+use std::process::exit;
+
+use synacor_vm::debugger::parse_number;
+use synacor_vm::map::{Explorer, ROOM_ADDRESS};
+use synacor_vm::terminal::load_script;
+use synacor_vm::VirtualMachine;
+
+const USAGE: &str = "Usage: explorer [--script <FILE>] [--load-snapshot <FILE>] [--max-rooms <N>] [--room-address <ADDR>] [--map <FILE>] [BINARY]\n\
+    --room-address is the memory word with the current room, 0x0AAC by default\n\
+    --map writes the map as JSON for a .json file, as a Graphviz graph otherwise";
+const DEFAULT_MAX_ROOMS: usize = 500;
+
+fn print(explorer: &Explorer) {
+    let map = explorer.map();
+    for (index, room) in map.rooms().iter().enumerate() {
+        println!("#{} {}", index, room.title);
+        if !room.items.is_empty() {
+            println!("    items: {}", room.items.join(", "));
+        }
+        for exit in &room.exits {
+            match map.destination(index, exit) {
+                Some(to) => println!("    {} -> #{} {}", exit, to, map.rooms()[to].title),
+                None => println!("    {} -> ?", exit),
+            }
+        }
+    }
+
+    for dead_end in explorer.dead_ends() {
+        println!("#{} {}: {}", dead_end.room, dead_end.exit, dead_end.reason);
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    exit(64);
+}
+
+fn main() {
+    let (mut binary, mut script, mut snapshot, mut max_rooms) = (String::from("challenge.bin"), None, None, DEFAULT_MAX_ROOMS);
+    let (mut map, mut room_address) = (None, ROOM_ADDRESS);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(format!("'{}' expects a value", arg)));
+        match arg.as_str() {
+            "--script" => script = Some(value()),
+            "--load-snapshot" => snapshot = Some(value()),
+            "--map" => map = Some(value()),
+            "--room-address" => {
+                let address = value();
+                room_address = parse_number(&address).unwrap_or_else(|| fail(format!("'{}' is not an address", address)));
+            }
+            "--max-rooms" => {
+                let rooms = value();
+                max_rooms = rooms.parse().unwrap_or_else(|_| fail(format!("'{}' is not a number of rooms", rooms)));
+            }
+            _ if arg.starts_with("--") => fail(format!("Unknown option '{}'", arg)),
+            _ => binary = arg,
+        }
+    }
+
+    let mut vm = VirtualMachine::default();
+    let loaded = match &snapshot {
+        Some(path) => vm.load_snapshot(path).map_err(|err| format!("Couldn't load the snapshot '{}': {:?}", path, err)),
+        None => VirtualMachine::get_binary_from_path(&binary)
+            .and_then(|data| vm.load_binary(|| data))
+            .map_err(|err| format!("Couldn't load the binary '{}': {:?}", binary, err)),
+    };
+    if let Err(err) = loaded {
+        eprintln!("{}", err);
+        exit(66);
+    }
+    if let Some(path) = &script {
+        vm.add_script(load_script(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't read the script '{}': {}", path, err);
+            exit(66);
+        }));
+    }
+
+    let mut explorer = Explorer::new(vm);
+    explorer.set_room_address(room_address);
+    if let Err(err) = explorer.explore(max_rooms) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+    print(&explorer);
+    if let Some(path) = map {
+        if let Err(err) = explorer.map().export(&path) {
+            eprintln!("Couldn't write the map '{}': {}", path, err);
+            exit(73);
+        }
+    }
+}
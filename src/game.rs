@@ -0,0 +1,170 @@
+//! Tracks the state of the adventure game from the text exchanged with the program.
+//!
+//! The VM only deals in characters, the [`GameState`] observes them and keeps the current room, its
+//! items and exits, and the inventory. The inventory is listed by `inv` and follows `take` and `drop`
+//! in between, so a script can ask e.g. whether the lantern is in the inventory:
+//!
+//! ```
+//! # use std::{cell::RefCell, rc::Rc};
+//! # use synacor_vm::{game::GameState, VirtualMachine};
+//! let mut vm = VirtualMachine::default();
+//! let state = Rc::new(RefCell::new(GameState::default()));
+//! vm.add_observer(state.clone());
+//! // ...
+//! if state.borrow().has("lantern") {
+//!     vm.add_script(vec![String::from("use lantern")]);
+//! }
+//! ```
+
+use crate::adventure::{parse_inventory, parse_room, Room};
+use crate::vm::Observer;
+
+/// The line that ends every answer of the game.
+pub const PROMPT: &str = "What do I do?";
+
+/// The room, the items and the inventory, it observes the [`VirtualMachine`](crate::VirtualMachine).
+#[derive(Debug, Default)]
+pub struct GameState {
+    room: Option<Room>,
+    inventory: Vec<String>,
+    output: Vec<u8>,
+    command: Vec<u8>,
+    // The last command typed, it is matched with the answer
+    last_command: String,
+}
+
+impl GameState {
+    /// Returns the room described last.
+    pub fn room(&self) -> Option<&Room> {
+        self.room.as_ref()
+    }
+
+    /// Returns the items of the current room.
+    pub fn items(&self) -> &[String] {
+        self.room.as_ref().map_or(&[], |room| &room.items)
+    }
+
+    /// Returns the exits of the current room.
+    pub fn exits(&self) -> &[String] {
+        self.room.as_ref().map_or(&[], |room| &room.exits)
+    }
+
+    pub fn inventory(&self) -> &[String] {
+        &self.inventory
+    }
+
+    /// Returns `true` if the item is in the inventory, e.g. `lantern` matches `empty lantern`.
+    pub fn has(&self, item: &str) -> bool {
+        self.inventory.iter().any(|known| known == item || known.ends_with(&format!(" {}", item)))
+    }
+
+    /// Returns a line with the room, its exits and items, and the inventory, `None` before the
+    /// first room is described.
+    pub fn status_line(&self) -> Option<String> {
+        let room = self.room.as_ref()?;
+        let mut status = format!("[{}] exits: {}", room.title, list(&room.exits));
+        if !room.items.is_empty() {
+            status.push_str(&format!(" | here: {}", list(&room.items)));
+        }
+        status.push_str(&format!(" | inventory: {}", list(&self.inventory)));
+
+        Some(status)
+    }
+
+    /// Forgets the room and the inventory, e.g. after the VM was restored to another state.
+    pub fn reset(&mut self) {
+        *self = GameState::default();
+    }
+
+    // Applies the answer to the last command
+    fn update(&mut self) {
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        if let Some(room) = parse_room(&output) {
+            self.room = Some(room);
+        }
+        if let Some(inventory) = parse_inventory(&output) {
+            self.inventory = inventory;
+        }
+
+        let answered = |answer: &str| output.lines().any(|line| line.trim() == answer);
+        let command = std::mem::take(&mut self.last_command);
+        if let (Some(item), true) = (command.strip_prefix("take "), answered("Taken.")) {
+            if let Some(room) = self.room.as_mut() {
+                room.items.retain(|known| known != item);
+            }
+            self.inventory.push(item.to_string());
+        } else if let (Some(item), true) = (command.strip_prefix("drop "), answered("Dropped.")) {
+            self.inventory.retain(|known| known != item);
+            if let Some(room) = self.room.as_mut() {
+                room.items.push(item.to_string());
+            }
+        }
+    }
+}
+
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        String::from("-")
+    } else {
+        items.join(", ")
+    }
+}
+
+impl Observer for GameState {
+    fn output(&mut self, _step: u64, c: u8) {
+        self.output.push(c);
+        if c == b'\n' && self.output.ends_with(format!("{}\n", PROMPT).as_bytes()) {
+            self.update();
+        }
+    }
+
+    fn input(&mut self, _step: u64, c: u8) {
+        if self.command.is_empty() && !self.output.is_empty() {
+            self.update();
+        }
+
+        if c == b'\n' {
+            self.last_command = String::from_utf8_lossy(&self.command).trim().to_string();
+            self.command.clear();
+        } else {
+            self.command.push(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(state: &mut GameState, output: &str, input: &str) {
+        output.bytes().for_each(|c| state.output(0, c));
+        input.bytes().for_each(|c| state.input(0, c));
+    }
+
+    #[test]
+    fn test_state() {
+        let mut state = GameState::default();
+        assert_eq!(state.status_line(), None);
+
+        play(&mut state, "== Foothills ==\nThe Foothills.\n\nThings of interest here:\n- tablet\n\n\
+            There are 2 exits:\n- doorway\n- south\n\nWhat do I do?\n", "take tablet\n");
+        assert_eq!(state.room().map(|room| room.title.as_str()), Some("Foothills"));
+        assert_eq!(state.items(), ["tablet"]);
+
+        play(&mut state, "\nTaken.\n\nWhat do I do?\n", "inv\n");
+        assert!(state.has("tablet"));
+        assert!(state.items().is_empty());
+
+        play(&mut state, "\nYour inventory:\n- tablet\n- empty lantern\n\nWhat do I do?\n", "drop tablet\n");
+        assert!(state.has("lantern"));
+        play(&mut state, "\nDropped.\n\nWhat do I do?\n", "");
+        assert!(!state.has("tablet"));
+        assert_eq!(state.status_line(),
+                   Some(String::from("[Foothills] exits: doorway, south | here: tablet | inventory: empty lantern")));
+
+        state.reset();
+        assert_eq!(state.status_line(), None);
+        assert!(state.inventory().is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+//! A virtual machine for the [Synacor challenge](https://challenge.synacor.com/).
+//!
+//! The crate exposes the building blocks used by the `synacor-vm` debugger so that other tools
+//! can load and run Synacor binaries without copying the sources around:
+//!
+//! * [`VirtualMachine`] - loads a binary and drives the execution;
+//! * [`CPU`] - decodes and executes the instructions, owns the registers and the stack;
+//! * [`Memory`] - the 15-bit address space shared by the VM and the CPU;
+//! * [`Debugger`] - the command language of the interactive debugger.
+//!
+//! ```no_run
+//! use synacor_vm::VirtualMachine;
+//!
+//! let mut vm = VirtualMachine::default();
+//! vm.load_binary(|| VirtualMachine::get_binary_from_path("challenge.bin").unwrap_or_default())
+//!     .expect("The binary couldn't be loaded");
+//! vm.run();
+//! ```
+
+pub mod adventure;
+pub mod codes;
+pub mod cpu;
+pub mod debugger;
+pub mod equation;
+pub mod game;
+pub mod map;
+pub mod mem;
+pub mod patch;
+pub mod replay;
+pub mod runner;
+pub mod slots;
+pub mod snapshot;
+pub mod terminal;
+pub mod timeline;
+pub mod transcript;
+pub mod vm;
+
+pub use crate::cpu::{CPU, CPUError, Frame, MAX_REGISTERS};
+pub use crate::debugger::Debugger;
+pub use crate::mem::{Memory, MemoryError, MAX_ADDRESS};
+pub use crate::snapshot::{Snapshot, SnapshotError};
+pub use crate::vm::{Hook, Observer, Stop, VirtualMachine, VirtualMachineError};
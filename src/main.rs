@@ -1,59 +1,249 @@
-use crate::vm::{VirtualMachine};
-use std::io::{self, Write};
+use synacor_vm::codes::CodeCollector;
+use synacor_vm::map::MapRecorder;
+use synacor_vm::patch::{Patch, PatchError};
+use synacor_vm::replay::{replay, Recorder, Recording};
+use synacor_vm::runner::{run_golden, run_headless, Outcome};
+use synacor_vm::slots::SaveSlots;
+use synacor_vm::terminal::{load_script, Keyboard, LineReader};
+use synacor_vm::transcript::Transcript;
+use synacor_vm::{Debugger, VirtualMachine};
+use crate::cli::{Options, USAGE};
+use log::LevelFilter;
+use std::env;
+use std::fs::{self, File};
+use std::cell::RefCell;
+use std::io::{self, BufReader, BufWriter, Write};
 use std::process::exit;
+use std::rc::Rc;
 
-mod vm;
-mod mem;
-mod cpu;
+mod cli;
 
 fn main() {
-    env_logger::builder()
-        .format_timestamp(None)
-        .init();
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(64);
+    });
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
 
-    println!("Let's start the VM!!!!");
+    let mut logger = env_logger::builder();
+    logger.format_timestamp(None);
+    if options.trace {
+        logger.filter_level(LevelFilter::Trace);
+    }
+    logger.init();
 
-    let mut vm = vm::VirtualMachine::default();
-    vm.load_binary(|| {
-        VirtualMachine::get_binary_from_path("challenge.bin").unwrap_or_else(|err| {
-            eprintln!("Couldn't load the test file: {:?}", err);
-            Vec::new()
-        })
-    }).expect("The file 'challenge.bin' couldn't be loaded");
+    let mut vm = VirtualMachine::default();
+    if let Some(snapshot) = &options.snapshot {
+        vm.load_snapshot(snapshot).unwrap_or_else(|err| {
+            eprintln!("Couldn't load the snapshot '{}': {:?}", snapshot, err);
+            exit(66);
+        });
+    } else {
+        let binary = VirtualMachine::get_binary_from_path(&options.binary).unwrap_or_else(|err| {
+            eprintln!("Couldn't load the binary '{}': {:?}", options.binary, err);
+            exit(66);
+        });
+        vm.load_binary(|| binary).unwrap_or_else(|err| {
+            eprintln!("The binary '{}' couldn't be loaded: {:?}", options.binary, err);
+            exit(66);
+        });
+    }
 
-    println!("Type 'exit' to hm... exit");
-    let mut buffer = String::new();
-    while let Ok(_) = io::stdin().read_line(&mut buffer) {
-        match buffer.trim_end() {
-            "exit" => break,
-            "regs" => vm.dump_registry(),
-            "where" => {
-                io::stdout().flush().unwrap();
-                println!("\n{0:#6} / {0:#06X}", vm.get_current_address());
+    for path in &options.patches {
+        let patch = Patch::load_from_file(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't read the patch '{}': {}", path, err);
+            exit(if matches!(err, PatchError::Io(_)) { 66 } else { 65 });
+        });
+        vm.apply_patch(&patch).unwrap_or_else(|err| {
+            eprintln!("Couldn't apply the patch '{}': {}", path, err);
+            exit(65);
+        });
+    }
+
+    if let Some(script) = &options.script {
+        let lines = load_script(script).unwrap_or_else(|err| {
+            eprintln!("Couldn't read the script '{}': {}", script, err);
+            exit(66);
+        });
+        vm.add_script(lines);
+        vm.set_script_echo(options.echo);
+        if options.headless {
+            vm.set_input(LineReader::new(io::empty()));
+        }
+    } else if options.headless {
+        vm.set_input(LineReader::new(BufReader::new(io::stdin())));
+    }
+    if let Some(output) = &options.output {
+        let file = File::create(output).unwrap_or_else(|err| {
+            eprintln!("Couldn't create the output '{}': {}", output, err);
+            exit(73);
+        });
+        vm.set_output(BufWriter::new(file));
+    }
+    let transcript = options.transcript.as_ref().map(|path| {
+        let transcript = Rc::new(RefCell::new(Transcript::create(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't create the transcript '{}': {}", path, err);
+            exit(73);
+        })));
+        vm.add_observer(transcript.clone());
+        transcript
+    });
+    let recorder = options.record.as_ref().map(|path| {
+        let recorder = Rc::new(RefCell::new(Recorder::create(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't create the recording '{}': {}", path, err);
+            exit(73);
+        })));
+        vm.add_observer(recorder.clone());
+        recorder
+    });
+    let codes = options.codes.as_ref().map(|path| {
+        let codes = Rc::new(RefCell::new(CodeCollector::open(path).unwrap_or_else(|err| {
+            eprintln!("Couldn't read the codes '{}': {}", path, err);
+            exit(66);
+        })));
+        vm.add_observer(codes.clone());
+        codes
+    });
+    let map = options.map.as_ref().map(|_| {
+        let map = Rc::new(RefCell::new(MapRecorder::default()));
+        vm.add_observer(map.clone());
+        map
+    });
+    options.breakpoints.iter().for_each(|&address| {
+        vm.add_breakpoint(address);
+    });
+    vm.set_step_limit(options.max_steps);
+
+    let exit_code = if let Some(code) = options.replay.as_ref()
+        .and_then(|path| replay_recording(&mut vm, path, options.fast_forward, options.headless)) {
+        code
+    } else if let Some(expect) = &options.expect {
+        check_transcript(&mut vm, expect)
+    } else if options.headless {
+        match run_headless(&mut vm) {
+            Outcome::Faulted(err) => {
+                eprintln!("The program has faulted at step {}: {:?}", vm.steps(), err);
+                Outcome::Faulted(err).exit_code()
             }
-            "run" => vm.run(),
-            buf @ _ => {
-                if buf.starts_with("until ") {
-                    if let Ok(pos) = u16::from_str_radix(buf.trim_start_matches("until 0x"), 16) {
-                        vm.run_until(pos);
-                    } else if let Ok(pos) = u16::from_str_radix(buf.trim_start_matches("until "), 10) {
-                        vm.run_until(pos);
-                    } else {
-                        eprintln!("Couldn't parse the command: {}", buf);
-                    }
-                } else {
-                    match vm.next_step() {
-                        Ok(to_stop) if to_stop => break,
-                        Err(err) => {
-                            eprintln!("Unexpected error: {:?}\n", err);
-                            exit(-1);
-                        }
-                        _ => {}
-                    }
-                }
+            outcome => outcome.exit_code(),
+        }
+    } else {
+        run_debugger(vm, options.saves.as_deref(), codes, map.clone())
+    };
+
+    if let Some(transcript) = transcript {
+        let _ = transcript.borrow_mut().flush();
+    }
+    if let Some(recorder) = recorder {
+        let _ = recorder.borrow_mut().flush();
+    }
+    if let (Some(path), Some(map)) = (&options.map, map) {
+        if let Err(err) = map.borrow_mut().map().export(path) {
+            eprintln!("Couldn't write the map '{}': {}", path, err);
+            exit(73);
+        }
+    }
+    exit(exit_code);
+}
+
+// Returns the exit code, or `None` to continue the fast-forwarded session
+fn replay_recording(vm: &mut VirtualMachine, path: &str, until: Option<u64>, headless: bool) -> Option<i32> {
+    let recording = Recording::load_from_file(path).unwrap_or_else(|err| {
+        eprintln!("Couldn't read the recording '{}': {:?}", path, err);
+        exit(66);
+    });
+
+    vm.set_input(LineReader::new(io::empty()));
+    match replay(vm, &recording, until) {
+        (Outcome::Faulted(err), _) => {
+            eprintln!("The program has faulted at step {}: {:?}", vm.steps(), err);
+            Some(1)
+        }
+        (_, Some(divergence)) => {
+            eprintln!("{}", divergence);
+            Some(4)
+        }
+        (Outcome::StepLimit, None) if until.is_none_or(|until| vm.steps() < until) => {
+            eprintln!("The step limit is reached at step {}, before the end of the replay", vm.steps());
+            Some(Outcome::StepLimit.exit_code())
+        }
+        (outcome, None) if until.is_none() || headless => {
+            println!("The replay matches '{}' ({:?} after {} steps)", path, outcome, vm.steps());
+            Some(0)
+        }
+        _ => {
+            vm.set_input(Keyboard::default());
+            None
+        }
+    }
+}
+
+fn check_transcript(vm: &mut VirtualMachine, expect: &str) -> i32 {
+    let expected = fs::read_to_string(expect).unwrap_or_else(|err| {
+        eprintln!("Couldn't read the transcript '{}': {}", expect, err);
+        exit(66);
+    });
+
+    match run_golden(vm, &expected) {
+        (Outcome::Faulted(err), _) => {
+            eprintln!("The program has faulted at step {}: {:?}", vm.steps(), err);
+            1
+        }
+        (_, Some(mismatch)) => {
+            eprintln!("{}", mismatch);
+            4
+        }
+        (outcome, None) => {
+            println!("The output matches '{}' ({:?} after {} steps)", expect, outcome, vm.steps());
+            0
+        }
+    }
+}
+
+fn run_debugger(vm: VirtualMachine, saves: Option<&str>, codes: Option<Rc<RefCell<CodeCollector>>>,
+                map: Option<Rc<RefCell<MapRecorder>>>) -> i32 {
+    println!("Let's start the VM!!!!");
+    println!("Type 'help' to see the commands or 'exit' to hm... exit");
+    println!("Type 'continue' to play, Ctrl-] and Enter return to the debugger");
+    println!("While playing, '!' runs a debugger command without leaving the game, e.g. '!save vault'");
+    let mut debugger = Debugger::new(vm);
+    if let Some(saves) = saves {
+        debugger.set_slots(SaveSlots::new(saves));
+    }
+    if let Some(codes) = codes {
+        debugger.set_codes(codes);
+    }
+    if let Some(map) = map {
+        debugger.set_map(map);
+    }
+    let mut buffer = String::new();
+    let mut status = None;
+    while prompt(&debugger, &mut status) && matches!(io::stdin().read_line(&mut buffer), Ok(size) if size > 0) {
+        match debugger.execute_line(buffer.trim()) {
+            Ok(to_exit) if to_exit => break,
+            Err(err) => {
+                eprintln!("Unexpected error: {:?}\n", err);
+                return -1;
             }
+            _ => {}
         }
 
         buffer.clear();
     }
+
+    0
+}
+
+// Shows the state of the game when it has changed since the last prompt
+fn prompt(debugger: &Debugger, last_status: &mut Option<String>) -> bool {
+    let status = debugger.state().status_line();
+    if status.is_some() && status != *last_status {
+        println!("{}", status.as_deref().unwrap_or_default());
+        *last_status = status;
+    }
+    print!("(svm) ");
+    io::stdout().flush().is_ok()
 }
@@ -0,0 +1,492 @@
+//! The map of the game: the rooms and the exits that lead from one room to another.
+//!
+//! The rooms of the twisty maze look alike, so the [`Explorer`] knows a room by the memory word in
+//! which the game keeps the current room, see [`ROOM_ADDRESS`]; the text of the room is only a
+//! label. The [`MapRecorder`] sees the text only, it merges the rooms with the same title,
+//! description and exits.
+//!
+//! The map is built by the explorer or by the recorder while the game is played, and exported as a
+//! Graphviz graph or as JSON, see [`Map::export`].
+
+use crate::adventure::{parse_room, Room};
+use crate::game::PROMPT;
+use crate::runner::{run_headless, Outcome};
+use crate::snapshot::Snapshot;
+use crate::terminal::{Capture, LineReader};
+use crate::vm::{Observer, VirtualMachine, VirtualMachineError};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The address of the word with the current room in the challenge binary.
+pub const ROOM_ADDRESS: u16 = 0x0AAC;
+/// The number of the instructions a move may take, the game answers within a few thousands.
+pub const MAX_STEPS_PER_MOVE: u64 = 5_000_000;
+
+/// The rooms and the exits between them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Map {
+    rooms: Vec<Room>,
+    // The value of the room word of every room, `None` if only the text is known
+    ids: Vec<Option<u16>>,
+    // The room and the exit taken from it, the room reached
+    exits: BTreeMap<(usize, String), usize>,
+}
+
+impl Map {
+    /// Returns the rooms in the order they were found.
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+
+    pub fn room(&self, index: usize) -> Option<&Room> {
+        self.rooms.get(index)
+    }
+
+    /// Returns the index of the room with the same room word, or with the same title, description
+    /// and exits if `id` is `None`.
+    pub fn find(&self, id: Option<u16>, room: &Room) -> Option<usize> {
+        match id {
+            Some(_) => self.ids.iter().position(|&known| known == id),
+            None => self.rooms.iter().zip(&self.ids).position(|(known, known_id)| {
+                known_id.is_none()
+                    && known.title == room.title && known.description == room.description && known.exits == room.exits
+            }),
+        }
+    }
+
+    /// Adds the room unless it is known, returns its index and `true` if it is new.
+    ///
+    /// `id` is the value of the room word, see [`Map::find`]. The items of a known room are
+    /// replaced, they are taken and dropped during the game.
+    pub fn add_room(&mut self, id: Option<u16>, room: Room) -> (usize, bool) {
+        match self.find(id, &room) {
+            Some(index) => {
+                self.rooms[index].items = room.items;
+                (index, false)
+            }
+            None => {
+                self.rooms.push(room);
+                self.ids.push(id);
+                (self.rooms.len() - 1, true)
+            }
+        }
+    }
+
+    /// Records that the exit of the room `from` leads to the room `to`.
+    pub fn connect(&mut self, from: usize, exit: &str, to: usize) {
+        self.exits.insert((from, exit.to_string()), to);
+    }
+
+    /// Returns the room reached by the exit, `None` if the exit wasn't taken yet.
+    pub fn destination(&self, from: usize, exit: &str) -> Option<usize> {
+        self.exits.get(&(from, exit.to_string())).copied()
+    }
+
+    /// Returns the exits taken: the room, the exit and the room reached.
+    pub fn exits(&self) -> impl Iterator<Item=(usize, &str, usize)> + '_ {
+        self.exits.iter().map(|((from, exit), &to)| (*from, exit.as_str(), to))
+    }
+
+    /// Returns the Graphviz graph: a node per room with its items, an edge per exit taken.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph map {\n    node [shape=box];\n");
+        for (index, room) in self.rooms.iter().enumerate() {
+            let mut label = room.title.clone();
+            room.items.iter().for_each(|item| label.push_str(&format!("\n- {}", item)));
+            let _ = writeln!(dot, "    r{} [label={}];", index, quote(&label));
+        }
+        for (from, exit, to) in self.exits() {
+            let _ = writeln!(dot, "    r{} -> r{} [label={}];", from, to, quote(exit));
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+
+    /// Returns the rooms as JSON, every exit leads to the index of a room or to `null` if it wasn't
+    /// taken yet.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"rooms\": [");
+        for (index, room) in self.rooms.iter().enumerate() {
+            let items: Vec<String> = room.items.iter().map(|item| quote(item)).collect();
+            let exits: Vec<String> = room.exits.iter()
+                .map(|exit| format!("{}: {}", quote(exit),
+                                    self.destination(index, exit).map_or(String::from("null"), |to| to.to_string())))
+                .collect();
+            let _ = write!(json, "{}\n    {{\n      \"id\": {},\n      \"title\": {},\n      \"description\": {},\n      \
+                                  \"items\": [{}],\n      \"exits\": {{{}}}\n    }}",
+                           if index == 0 { "" } else { "," }, index, quote(&room.title), quote(&room.description),
+                           items.join(", "), exits.join(", "));
+        }
+        json.push_str(if self.rooms.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+
+        json
+    }
+
+    /// Writes the map to a file, as JSON if the extension is `json`, as a Graphviz graph otherwise.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => fs::write(path, self.to_json()),
+            _ => fs::write(path, self.to_dot()),
+        }
+    }
+}
+
+// A string literal, it is valid in JSON and in Graphviz
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Builds the map while the game is played, it observes the [`VirtualMachine`](crate::VirtualMachine).
+///
+/// A room described after a move, e.g. `go north` or `north`, is connected to the previous room.
+#[derive(Debug, Default)]
+pub struct MapRecorder {
+    map: Map,
+    output: Vec<u8>,
+    command: Vec<u8>,
+    current: Option<usize>,
+    exit: Option<String>,
+}
+
+impl MapRecorder {
+    /// Returns the map, the room described last is included.
+    pub fn map(&mut self) -> &Map {
+        self.update();
+        &self.map
+    }
+
+    /// Forgets the current room, e.g. after the VM was restored to another state; the map is kept
+    /// and the next room described isn't connected to the previous one.
+    pub fn reset(&mut self) {
+        self.output.clear();
+        self.command.clear();
+        self.current = None;
+        self.exit = None;
+    }
+
+    // Adds the room described since the last command
+    fn update(&mut self) {
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        if let Some(room) = parse_room(&output) {
+            let (to, _) = self.map.add_room(None, room);
+            if let (Some(from), Some(exit)) = (self.current, self.exit.take()) {
+                self.map.connect(from, &exit, to);
+            }
+            self.current = Some(to);
+        }
+        self.exit = None;
+    }
+}
+
+impl Observer for MapRecorder {
+    fn output(&mut self, _step: u64, c: u8) {
+        self.output.push(c);
+    }
+
+    fn input(&mut self, _step: u64, c: u8) {
+        if self.command.is_empty() {
+            self.update();
+        }
+
+        if c != b'\n' {
+            self.command.push(c);
+            return;
+        }
+        let command = String::from_utf8_lossy(&self.command).trim().to_string();
+        self.command.clear();
+        let exits = self.current.and_then(|current| self.map.room(current)).map(|room| &room.exits);
+        self.exit = match command.strip_prefix("go ") {
+            Some(exit) => Some(exit.trim().to_string()),
+            None => exits.filter(|exits| exits.contains(&command)).map(|_| command),
+        };
+    }
+}
+
+/// An exit that doesn't lead to a room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadEnd {
+    /// The index of the room in the [`Map`].
+    pub room: usize,
+    pub exit: String,
+    /// Why no room was reached, e.g. the game has ended.
+    pub reason: String,
+}
+
+/// Errors reported by the [`Explorer`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ExploreError {
+    /// The game doesn't wait for a command at the start, contains the reason.
+    NotWaiting(String),
+    /// The game doesn't describe the room at the start.
+    NoRoom,
+    /// The snapshot of a room couldn't be restored.
+    Restore(VirtualMachineError),
+}
+
+impl fmt::Display for ExploreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExploreError::NotWaiting(reason) => write!(f, "The game doesn't wait for a command: {}", reason),
+            ExploreError::NoRoom => write!(f, "The game doesn't describe a room"),
+            ExploreError::Restore(err) => write!(f, "Couldn't restore the snapshot: {:?}", err),
+        }
+    }
+}
+
+/// Maps the game breadth-first: takes every listed exit of every room, reads the room it gets to
+/// and restores the snapshot of the previous room to try the other exits.
+///
+/// The exploration starts from the current state of the VM, the exits that need an item, e.g. the
+/// lantern in the dark passages, depend on it.
+pub struct Explorer {
+    vm: VirtualMachine,
+    output: Capture,
+    room_address: u16,
+    map: Map,
+    dead_ends: Vec<DeadEnd>,
+}
+
+impl Explorer {
+    /// Takes over the VM: the output is captured and the input ends after the queued script.
+    pub fn new(mut vm: VirtualMachine) -> Explorer {
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.set_input(LineReader::new(io::empty()));
+
+        Explorer { vm, output, room_address: ROOM_ADDRESS, map: Map::default(), dead_ends: Vec::new() }
+    }
+
+    /// Sets the address of the word with the current room, [`ROOM_ADDRESS`] by default.
+    pub fn set_room_address(&mut self, address: u16) {
+        self.room_address = address;
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    /// Returns the exits that don't lead to a room, in the order they were taken.
+    pub fn dead_ends(&self) -> &[DeadEnd] {
+        &self.dead_ends
+    }
+
+    /// Explores the game, the rooms found after the first `max_rooms` ones are not explored.
+    pub fn explore(&mut self, max_rooms: usize) -> Result<(), ExploreError> {
+        let output = self.play(Vec::new()).map_err(ExploreError::NotWaiting)?;
+        let room = match parse_room(&output) {
+            Some(room) => room,
+            None => parse_room(&self.play(vec![String::from("look")]).map_err(ExploreError::NotWaiting)?)
+                .ok_or(ExploreError::NoRoom)?,
+        };
+
+        let (start, _) = self.map.add_room(Some(self.room_id()), room);
+        let mut queue: VecDeque<(usize, Snapshot)> = VecDeque::new();
+        queue.push_back((start, self.vm.snapshot()));
+
+        while let Some((from, snapshot)) = queue.pop_front() {
+            let exits = self.map.room(from).map(|room| room.exits.clone()).unwrap_or_default();
+            for exit in exits {
+                self.vm.restore(&snapshot).map_err(ExploreError::Restore)?;
+                let room = self.play(vec![format!("go {}", exit)])
+                    .and_then(|output| parse_room(&output).ok_or_else(|| format!("no room: {}", last_line(&output))));
+
+                match room {
+                    Ok(room) => {
+                        let (to, new) = self.map.add_room(Some(self.room_id()), room);
+                        self.map.connect(from, &exit, to);
+                        if new && self.map.rooms().len() <= max_rooms {
+                            queue.push_back((to, self.vm.snapshot()));
+                        }
+                    }
+                    Err(reason) => self.dead_ends.push(DeadEnd { room: from, exit, reason }),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn room_id(&self) -> u16 {
+        self.vm.cpu.get_value_from_address(self.room_address).unwrap_or_default()
+    }
+
+    // Plays the lines and returns the output, `Err` with the reason if the game doesn't wait for more
+    fn play(&mut self, lines: Vec<String>) -> Result<String, String> {
+        self.output.clear();
+        self.vm.add_script(lines);
+        self.vm.set_step_limit(Some(self.vm.steps() + MAX_STEPS_PER_MOVE));
+
+        match run_headless(&mut self.vm) {
+            Outcome::EndOfInput => Ok(self.output.text()),
+            Outcome::Halted => Err(format!("the game has ended: {}", last_line(&self.output.text()))),
+            Outcome::StepLimit => Err(String::from("the game doesn't answer")),
+            Outcome::Faulted(err) => Err(format!("the program has faulted: {:?}", err)),
+        }
+    }
+}
+
+// The last line of the answer before the prompt
+fn last_line(output: &str) -> &str {
+    output.lines().rev().map(str::trim).find(|line| !line.is_empty() && *line != PROMPT).unwrap_or("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(title: &str, items: &[&str], exits: &[&str]) -> Room {
+        Room {
+            title: title.to_string(),
+            description: format!("The {}.", title),
+            items: items.iter().map(|item| item.to_string()).collect(),
+            exits: exits.iter().map(|exit| exit.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_map() {
+        let mut map = Map::default();
+        assert_eq!(map.add_room(None, room("Foothills", &["tablet"], &["doorway", "south"])), (0, true));
+        assert_eq!(map.add_room(None, room("Dark cave", &[], &["south"])), (1, true));
+        assert_eq!(map.add_room(None, room("Foothills", &[], &["doorway", "south"])), (0, false));
+        assert_eq!(map.add_room(None, room("Foothills", &[], &["south"])), (2, true));
+        assert!(map.room(0).is_some_and(|room| room.items.is_empty()));
+
+        assert_eq!(map.add_room(Some(7), room("Maze", &[], &["north"])), (3, true));
+        assert_eq!(map.add_room(Some(8), room("Maze", &[], &["north"])), (4, true));
+        assert_eq!(map.add_room(Some(7), room("Maze", &["lamp"], &["north"])), (3, false));
+        assert_eq!(map.find(None, &room("Maze", &[], &["north"])), None);
+
+        map.connect(0, "doorway", 1);
+        map.connect(1, "south", 0);
+        assert_eq!(map.destination(0, "doorway"), Some(1));
+        assert_eq!(map.destination(0, "south"), None);
+        assert_eq!(map.exits().collect::<Vec<_>>(), [(0, "doorway", 1), (1, "south", 0)]);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut map = Map::default();
+        map.add_room(None, room("Foothills", &["tablet"], &["doorway", "south"]));
+        map.add_room(None, room("Dark \"cave\"", &[], &["south"]));
+        map.connect(0, "doorway", 1);
+
+        assert_eq!(map.to_dot(), "digraph map {\n    node [shape=box];\n\
+            \x20   r0 [label=\"Foothills\\n- tablet\"];\n\
+            \x20   r1 [label=\"Dark \\\"cave\\\"\"];\n\
+            \x20   r0 -> r1 [label=\"doorway\"];\n}\n");
+        assert_eq!(map.to_json(), "{\n  \"rooms\": [\n\
+            \x20   {\n      \"id\": 0,\n      \"title\": \"Foothills\",\n      \"description\": \"The Foothills.\",\n\
+            \x20     \"items\": [\"tablet\"],\n      \"exits\": {\"doorway\": 1, \"south\": null}\n    },\n\
+            \x20   {\n      \"id\": 1,\n      \"title\": \"Dark \\\"cave\\\"\",\n      \"description\": \"The Dark \\\"cave\\\".\",\n\
+            \x20     \"items\": [],\n      \"exits\": {\"south\": null}\n    }\n  ]\n}\n");
+        assert_eq!(Map::default().to_json(), "{\n  \"rooms\": []\n}\n");
+    }
+
+    #[test]
+    fn test_recorder() {
+        fn play(recorder: &mut MapRecorder, output: &str, input: &str) {
+            output.bytes().for_each(|c| recorder.output(0, c));
+            input.bytes().for_each(|c| recorder.input(0, c));
+        }
+        let mut recorder = MapRecorder::default();
+        play(&mut recorder, "== Foothills ==\nThe Foothills.\n\nThere are 2 exits:\n- doorway\n- south\n\nWhat do I do?\n", "doorway\n");
+        play(&mut recorder, "== Dark cave ==\nThe Dark cave.\n\nThere is 1 exit:\n- south\n\nWhat do I do?\n", "take lamp\n");
+        play(&mut recorder, "You can't take that.\n", "go south\n");
+        play(&mut recorder, "== Foothills ==\nThe Foothills.\n\nThere are 2 exits:\n- doorway\n- south\n\nWhat do I do?\n", "look\n");
+        play(&mut recorder, "== Foothills ==\nThe Foothills.\n\nThere are 2 exits:\n- doorway\n- south\n\nWhat do I do?\n", "");
+
+        assert_eq!(recorder.map().rooms().len(), 2);
+
+        recorder.reset();
+        play(&mut recorder, "", "south\n");
+        play(&mut recorder, "== Dark cave ==\nThe Dark cave.\n\nThere is 1 exit:\n- south\n\nWhat do I do?\n", "");
+        let map = recorder.map();
+        assert_eq!(map.rooms().len(), 2);
+        assert_eq!(map.exits().collect::<Vec<_>>(), [(0, "doorway", 1), (1, "south", 0)]);
+    }
+
+    // The word with the room in the maze game
+    const ROOM: u16 = 0x4000;
+    const R0: u16 = 0x8000;
+    const R1: u16 = 0x8001;
+    const R2: u16 = 0x8002;
+    const R3: u16 = 0x8003;
+    const MAZE: &str = "== Maze ==\nAll alike.\n\nThere are 2 exits:\n- north\n- south\n\nWhat do I do?\n";
+
+    // A game with a room and two maze rooms that look alike, `go <exit>` is matched by the first
+    // letter of the exit: 0 -north-> 1, 1 -north-> 2, 1 -south-> 0, 2 -north-> 2, 2 -south-> halt
+    fn maze() -> Vec<u16> {
+        let rooms = ["== Start ==\nThe start.\n\nThere is 1 exit:\n- north\n\nWhat do I do?\n", MAZE, MAZE];
+        let exits = [(0, b'n', 1), (1, b'n', 2), (1, b's', 0), (2, b'n', 2)];
+
+        let mut words = vec![15, R0, ROOM];
+        let mut to_read = Vec::new();
+        for (index, text) in rooms.iter().enumerate() {
+            words.extend_from_slice(&[4, R1, R0, index as u16, 8, R1, 0]);
+            let next = words.len() - 1;
+            text.bytes().for_each(|c| words.extend_from_slice(&[19, c as u16]));
+            words.extend_from_slice(&[6, 0]);
+            to_read.push(words.len() - 1);
+            words[next] = words.len() as u16;
+        }
+
+        let read = words.len() as u16;
+        to_read.iter().for_each(|&index| words[index] = read);
+        words.extend_from_slice(&[20, R2, 20, R2, 20, R2, 20, R3]);
+        let skip = words.len() as u16;
+        words.extend_from_slice(&[20, R2, 4, R1, R2, 10, 8, R1, skip]);
+        for &(from, letter, to) in &exits {
+            words.extend_from_slice(&[4, R1, R0, from, 8, R1, 0]);
+            let other_room = words.len() - 1;
+            words.extend_from_slice(&[4, R1, R3, letter as u16, 8, R1, 0]);
+            let other_exit = words.len() - 1;
+            words.extend_from_slice(&[16, ROOM, to, 6, 0]);
+            words[other_room] = words.len() as u16;
+            words[other_exit] = words.len() as u16;
+        }
+        words.push(0);
+
+        words
+    }
+
+    #[test]
+    fn test_explore() {
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(maze).expect("The binary should load without errors");
+        let mut explorer = Explorer::new(vm);
+        explorer.set_room_address(ROOM);
+        explorer.explore(10).expect("The game must be explored");
+
+        let titles: Vec<&str> = explorer.map().rooms().iter().map(|room| room.title.as_str()).collect();
+        assert_eq!(titles, ["Start", "Maze", "Maze"]);
+        assert_eq!(explorer.map().exits().collect::<Vec<_>>(),
+                   [(0, "north", 1), (1, "north", 2), (1, "south", 0), (2, "north", 2)]);
+        assert_eq!(explorer.dead_ends(), [DeadEnd {
+            room: 2,
+            exit: String::from("south"),
+            reason: String::from("the game has ended: -"),
+        }]);
+    }
+}
@@ -1,10 +1,17 @@
+/// The number of 16-bit words addressable by the VM (15-bit address space).
 pub const MAX_ADDRESS: usize = 0x8000;
 
+/// Errors reported by [`Memory`].
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum MemoryError {
+    /// The data doesn't fit into the memory, contains the length of the data.
     DataIsTooLarge(usize),
+    /// The address is outside of the 15-bit address space.
     OverflowAddress(u16),
 }
 
+/// The main memory of the VM: `MAX_ADDRESS` words of 16 bits each.
 pub struct Memory {
     memory: [u16; MAX_ADDRESS],
 }
@@ -18,6 +25,7 @@ impl Default for Memory {
 }
 
 impl Memory {
+    /// Copies `data` to the beginning of the memory, the rest of the memory is left untouched.
     pub fn load_data(&mut self, data: &[u16]) -> Result<(), MemoryError> {
         if data.len() > self.memory.len() {
             return Err(MemoryError::DataIsTooLarge(data.len()));
@@ -28,6 +36,12 @@ impl Memory {
         Ok(())
     }
 
+    /// Returns the whole memory.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.memory
+    }
+
+    /// Writes `value` at `address` and returns the previous value.
     pub fn set_value(&mut self, address: u16, value: u16) -> Result<u16, MemoryError> {
         match address {
             0..=0x7FFF => self.write_memory(address, value),
@@ -35,6 +49,7 @@ impl Memory {
         }
     }
 
+    /// Reads the word at `address`, `None` if the address is out of the memory.
     pub fn read_memory(&self, address: u16) -> Option<u16> {
         match address {
             0..=0x7FFF => Some(self.memory[address as usize]),
@@ -42,6 +57,7 @@ impl Memory {
         }
     }
 
+    /// Writes `value` at `address` and returns the previous value.
     pub fn write_memory(&mut self, address: u16, value: u16) -> Result<u16, MemoryError> {
         match address {
             0..=0x7FFF if address < MAX_ADDRESS as u16 => {
@@ -0,0 +1,312 @@
+//! Patch files: changes of the bytecode applied after the binary is loaded.
+//!
+//! Every line is an address, the original words and the new ones:
+//!
+//! ```text
+//! # Return from the confirmation of the teleporter at once
+//! 0x178B: jt r0 0x1793; add r0 r1 1 => set r0 6; ret
+//! # Or skip the call
+//! 0x1571: 0x0011 0x178B => 21 21
+//! ```
+//!
+//! The words are numbers or instructions with their operands, the registers are `r0..r7` and a
+//! character is written as `'a'`, `'#'` and `' '` included. The original words are checked before anything is written, the
+//! new words cannot be longer than them.
+
+use crate::cpu::{MNEMONICS, OPERANDS};
+use crate::debugger::parse_number;
+use crate::mem::{Memory, MAX_ADDRESS};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const SEPARATOR: &str = "=>";
+
+/// Errors reported while reading or applying a [`Patch`], the lines start from 1.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PatchError {
+    Io(io::Error),
+    /// The line has no address, no `:` or no `=>`.
+    InvalidFormat(usize),
+    /// The word is neither a number, a register, a character nor an instruction.
+    InvalidWord { line: usize, word: String },
+    /// The instruction misses some of its operands.
+    MissingOperand { line: usize, instruction: String },
+    /// The new words are longer than the original ones.
+    TooLong(usize),
+    /// The words don't fit into the memory.
+    OutOfMemory(usize),
+    /// The memory doesn't contain the original words.
+    Mismatch { line: usize, address: u16, expected: Vec<u16>, actual: Vec<u16> },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Io(err) => write!(f, "{}", err),
+            PatchError::InvalidFormat(line) => write!(f, "Line {}: expected '<address>: <words> => <words>'", line),
+            PatchError::InvalidWord { line, word } => write!(f, "Line {}: '{}' is not a word", line, word),
+            PatchError::MissingOperand { line, instruction } =>
+                write!(f, "Line {}: '{}' misses an operand", line, instruction),
+            PatchError::TooLong(line) => write!(f, "Line {}: the new words are longer than the original ones", line),
+            PatchError::OutOfMemory(line) => write!(f, "Line {}: the words don't fit into the memory", line),
+            PatchError::Mismatch { line, address, expected, actual } =>
+                write!(f, "Line {}: expected {:04X?} at {:#06X}, found {:04X?}", line, expected, address, actual),
+        }
+    }
+}
+
+impl From<io::Error> for PatchError {
+    fn from(err: io::Error) -> Self {
+        PatchError::Io(err)
+    }
+}
+
+/// A line of a patch file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// The line in the file, starting from 1.
+    pub line: usize,
+    pub address: u16,
+    pub original: Vec<u16>,
+    pub replacement: Vec<u16>,
+}
+
+impl Change {
+    // The words after the change: the rest of the original words are kept
+    fn patched(&self) -> Vec<u16> {
+        let mut words = self.replacement.clone();
+        words.extend_from_slice(&self.original[self.replacement.len()..]);
+        words
+    }
+}
+
+/// The changes of a patch file, see the [module](self) for the format.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    changes: Vec<Change>,
+}
+
+impl Patch {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Patch, PatchError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Checks the original words of every change, then writes the new words.
+    ///
+    /// A change that is already applied, e.g. in a snapshot of a patched binary, is skipped.
+    /// Returns the number of the changes written, nothing is written if a check fails.
+    pub fn apply(&self, memory: &mut Memory) -> Result<usize, PatchError> {
+        let mut pending = Vec::new();
+        for change in &self.changes {
+            let start = change.address as usize;
+            let actual = &memory.as_slice()[start..start + change.original.len()];
+            if actual == change.original.as_slice() {
+                pending.push(change);
+            } else if actual != change.patched().as_slice() {
+                return Err(PatchError::Mismatch {
+                    line: change.line,
+                    address: change.address,
+                    expected: change.original.clone(),
+                    actual: actual.to_vec(),
+                });
+            }
+        }
+
+        for change in &pending {
+            for (address, &word) in (change.address..).zip(&change.replacement) {
+                memory.write_memory(address, word).or(Err(PatchError::OutOfMemory(change.line)))?;
+            }
+        }
+
+        Ok(pending.len())
+    }
+}
+
+impl FromStr for Patch {
+    type Err = PatchError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut changes = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (address, words) = line.split_once(':').ok_or(PatchError::InvalidFormat(number))?;
+            let (original, replacement) = words.split_once(SEPARATOR).ok_or(PatchError::InvalidFormat(number))?;
+            let change = Change {
+                line: number,
+                address: parse_number(address.trim()).ok_or(PatchError::InvalidFormat(number))?,
+                original: assemble(number, original)?,
+                replacement: assemble(number, replacement)?,
+            };
+
+            if change.replacement.len() > change.original.len() {
+                return Err(PatchError::TooLong(number));
+            }
+            if change.address as usize + change.original.len() > MAX_ADDRESS {
+                return Err(PatchError::OutOfMemory(number));
+            }
+            changes.push(change);
+        }
+
+        Ok(Patch { changes })
+    }
+}
+
+// Cuts the comment off, a `#` character isn't one
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'#' => return &line[..index],
+            _ if is_character(bytes, index) => index += 3,
+            _ => index += 1,
+        }
+    }
+
+    line
+}
+
+// The words are separated by the whitespace, ',' or ';'
+fn is_separator(c: u8) -> bool {
+    c.is_ascii_whitespace() || c == b',' || c == b';'
+}
+
+// Returns `true` if a character like `'a'` or `' '` starts at `index` and ends the word
+fn is_character(bytes: &[u8], index: usize) -> bool {
+    bytes[index] == b'\''
+        && bytes.get(index + 2) == Some(&b'\'')
+        && bytes.get(index + 3).is_none_or(|&c| is_separator(c))
+}
+
+// Splits the text into the words, a character can be a separator itself
+fn tokenize(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let end = if is_character(bytes, start) {
+            start + 3
+        } else {
+            bytes[start..].iter().position(|&c| is_separator(c)).map_or(bytes.len(), |length| start + length)
+        };
+
+        if end > start {
+            tokens.push(&text[start..end]);
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    tokens
+}
+
+// Converts the numbers and the instructions with their operands to words
+fn assemble(line: usize, text: &str) -> Result<Vec<u16>, PatchError> {
+    let mut words = Vec::new();
+    let mut tokens = tokenize(text).into_iter();
+
+    while let Some(token) = tokens.next() {
+        match MNEMONICS.iter().position(|&mnemonic| mnemonic == token) {
+            Some(op_code) => {
+                words.push(op_code as u16);
+                for _ in 0..OPERANDS[op_code] {
+                    let operand = tokens.next()
+                        .ok_or_else(|| PatchError::MissingOperand { line, instruction: token.to_string() })?;
+                    words.push(parse_word(line, operand)?);
+                }
+            }
+            None => words.push(parse_word(line, token)?),
+        }
+    }
+
+    Ok(words)
+}
+
+fn parse_word(line: usize, token: &str) -> Result<u16, PatchError> {
+    let register = token.strip_prefix('r')
+        .and_then(|number| number.parse::<u16>().ok())
+        .filter(|&number| number < 8)
+        .map(|number| 0x8000 + number);
+    let character = token.strip_prefix('\'')
+        .and_then(|rest| rest.strip_suffix('\''))
+        .filter(|c| c.len() == 1)
+        .map(|c| c.as_bytes()[0] as u16);
+
+    register.or(character)
+        .or_else(|| parse_number(token))
+        .ok_or_else(|| PatchError::InvalidWord { line, word: token.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let patch: Patch = "# The teleporter\n\
+            0x178B: jt r0 0x1793; add r0 r1 1 => set r0 6; ret\n\
+            \n\
+            10: 19 'a' => noop noop  # the output\n".parse().expect("The patch is valid");
+
+        assert_eq!(patch.changes(), [
+            Change { line: 2, address: 0x178B, original: vec![7, 0x8000, 0x1793, 9, 0x8000, 0x8001, 1], replacement: vec![1, 0x8000, 6, 18] },
+            Change { line: 4, address: 10, original: vec![19, 97], replacement: vec![21, 21] },
+        ].as_ref());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!("12 noop => halt".parse::<Patch>(), Err(PatchError::InvalidFormat(1))));
+        assert!(matches!("12: noop halt".parse::<Patch>(), Err(PatchError::InvalidFormat(1))));
+        assert!(matches!("\n12: jmp => halt".parse::<Patch>(), Err(PatchError::MissingOperand { line: 2, .. })));
+        assert!(matches!("12: r8 => halt".parse::<Patch>(), Err(PatchError::InvalidWord { line: 1, .. })));
+        assert!(matches!("12: noop => halt halt".parse::<Patch>(), Err(PatchError::TooLong(1))));
+        assert!(matches!("0x7FFF: noop noop => halt".parse::<Patch>(), Err(PatchError::OutOfMemory(1))));
+        assert!(matches!("12: out 'a'b => halt".parse::<Patch>(), Err(PatchError::InvalidWord { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_parse_characters() {
+        let patch: Patch = "10: out '#' out ' ' => out ';' out ','  # the separators\n\
+            20: eq r0 r1 '''; jt r0 ':' => noop".parse().expect("The patch is valid");
+
+        assert_eq!(patch.changes(), [
+            Change { line: 1, address: 10, original: vec![19, 35, 19, 32], replacement: vec![19, 59, 19, 44] },
+            Change { line: 2, address: 20, original: vec![4, 0x8000, 0x8001, 39, 7, 0x8000, 58], replacement: vec![21] },
+        ].as_ref());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut memory = Memory::default();
+        memory.load_data(&[17, 6, 21, 0, 0, 0, 18]).expect("The data fits");
+        let patch: Patch = "0: call 6 => 21\n6: ret => halt".parse().expect("The patch is valid");
+
+        assert_eq!(patch.apply(&mut memory).expect("The original words match"), 2);
+        assert_eq!(&memory.as_slice()[..7], [21, 6, 21, 0, 0, 0, 0]);
+        assert_eq!(patch.apply(&mut memory).expect("The patch is already applied"), 0);
+
+        let patch: Patch = "2: noop => halt\n3: out 'a' => noop".parse().expect("The patch is valid");
+        match patch.apply(&mut memory) {
+            Err(PatchError::Mismatch { line, address, expected, actual }) => {
+                assert_eq!((line, address, expected, actual), (2, 3, vec![19, 97], vec![0, 0]));
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert_eq!(memory.read_memory(2), Some(21));
+    }
+}
@@ -0,0 +1,357 @@
+//! Deterministic record and replay of sessions.
+//!
+//! The [`Recorder`] writes every character consumed by `in` and produced by `out` with the
+//! number of instructions executed before it:
+//!
+//! ```text
+//! # synacor-vm recording
+//! < 512300 10
+//! > 512403 116
+//! ```
+//!
+//! The program is deterministic, so feeding the recorded input to the same binary must
+//! reproduce the session exactly, [`replay`] checks every character and its step.
+
+use crate::runner::{run_headless, Outcome};
+use crate::vm::{Observer, VirtualMachine};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The first line of every recording.
+pub const HEADER: &str = "# synacor-vm recording";
+
+const OUTPUT: &str = "<";
+const INPUT: &str = ">";
+
+/// The direction of a recorded character.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Consumed by `in`.
+    Input,
+    /// Produced by `out`.
+    Output,
+}
+
+/// A recorded character.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    /// The number of instructions executed before the character was consumed or produced.
+    pub step: u64,
+    pub direction: Direction,
+    pub c: u8,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        };
+        write!(f, "{} {:?} at step {}", direction, self.c as char, self.step)
+    }
+}
+
+/// Errors reported while reading a [`Recording`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RecordingError {
+    /// The recording file couldn't be read.
+    Io(io::Error),
+    /// The line, starting from 1, isn't a recorded character.
+    InvalidFormat(usize),
+}
+
+/// Writes the recording of a session, it observes the [`VirtualMachine`].
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates the recording file, the file is overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Recorder::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Recorder { writer })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Observer for Recorder<W> {
+    fn output(&mut self, step: u64, c: u8) {
+        let _ = writeln!(self.writer, "{} {} {}", OUTPUT, step, c);
+    }
+
+    fn input(&mut self, step: u64, c: u8) {
+        let _ = writeln!(self.writer, "{} {} {}", INPUT, step, c);
+    }
+}
+
+impl<W: Write> Drop for Recorder<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// The characters of a recorded session.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    events: Vec<Event>,
+}
+
+impl Recording {
+    pub fn new(events: Vec<Event>) -> Recording {
+        Recording { events }
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns the recorded input split into lines, without the line endings.
+    pub fn input_lines(&self) -> Vec<String> {
+        let input: Vec<u8> = self.events.iter()
+            .filter(|event| event.direction == Direction::Input)
+            .map(|event| event.c)
+            .collect();
+
+        input.split_inclusive(|&c| c == b'\n')
+            .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line)).into_owned())
+            .collect()
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Recording, RecordingError> {
+        let file = File::open(path).map_err(RecordingError::Io)?;
+        Recording::read(BufReader::new(file))
+    }
+
+    /// Reads a recording written by the [`Recorder`], the empty lines and comments are skipped.
+    pub fn read<R: BufRead>(reader: R) -> Result<Recording, RecordingError> {
+        let mut events = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(RecordingError::Io)?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let direction = match fields.next() {
+                Some(OUTPUT) => Direction::Output,
+                Some(INPUT) => Direction::Input,
+                _ => return Err(RecordingError::InvalidFormat(index + 1)),
+            };
+            let step = fields.next().and_then(|step| step.parse().ok());
+            let c = fields.next().and_then(|c| c.parse().ok());
+            match (step, c, fields.next()) {
+                (Some(step), Some(c), None) => events.push(Event { step, direction, c }),
+                _ => return Err(RecordingError::InvalidFormat(index + 1)),
+            }
+        }
+
+        Ok(Recording { events })
+    }
+}
+
+/// The first character where the replay differs from the recording.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// The index of the recorded character.
+    pub index: usize,
+    /// The recorded character, `None` if the replay produced more than the recording.
+    pub expected: Option<Event>,
+    /// The replayed character, `None` if the replay ended before the recording.
+    pub actual: Option<Event>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "The replay diverges from the recording at character {}:", self.index + 1)?;
+        match &self.expected {
+            Some(event) => writeln!(f, "- {}", event)?,
+            None => writeln!(f, "- <end of recording>")?,
+        }
+        match &self.actual {
+            Some(event) => write!(f, "+ {}", event),
+            None => write!(f, "+ <end of replay>"),
+        }
+    }
+}
+
+// Compares the replayed characters with the recording, up to the first divergence
+struct Verifier {
+    expected: Vec<Event>,
+    position: usize,
+    divergence: Option<Divergence>,
+}
+
+impl Verifier {
+    fn check(&mut self, actual: Event) {
+        if self.divergence.is_some() {
+            return;
+        }
+
+        let expected = self.expected.get(self.position).copied();
+        if expected != Some(actual) {
+            self.divergence = Some(Divergence { index: self.position, expected, actual: Some(actual) });
+        }
+        self.position += 1;
+    }
+}
+
+impl Observer for Verifier {
+    fn output(&mut self, step: u64, c: u8) {
+        self.check(Event { step, direction: Direction::Output, c });
+    }
+
+    fn input(&mut self, step: u64, c: u8) {
+        self.check(Event { step, direction: Direction::Input, c });
+    }
+}
+
+/// Replays the recorded input and checks that the program consumes and produces the same
+/// characters at the same steps.
+///
+/// The VM must start from the state the session was recorded from and its input source should be
+/// empty, so the replay ends with the recording. With `until` the replay stops before that step,
+/// e.g. to fast-forward an old session and continue it in the debugger; the unused input is discarded.
+/// The step limit of the VM stops the replay as well, it is kept after the replay.
+pub fn replay(vm: &mut VirtualMachine, recording: &Recording, until: Option<u64>) -> (Outcome, Option<Divergence>) {
+    let step_limit = vm.step_limit();
+    let until = match (until, step_limit) {
+        (Some(until), Some(limit)) => Some(until.min(limit)),
+        (until, limit) => until.or(limit),
+    };
+    let expected: Vec<Event> = recording.events.iter()
+        .filter(|event| until.is_none_or(|until| event.step < until))
+        .copied()
+        .collect();
+    let verifier = Rc::new(RefCell::new(Verifier { expected, position: 0, divergence: None }));
+    let observer: Rc<RefCell<dyn Observer>> = verifier.clone();
+    vm.add_observer(observer.clone());
+    vm.add_script(recording.input_lines());
+    vm.set_step_limit(until);
+
+    let outcome = run_headless(vm);
+
+    vm.remove_observer(&observer);
+    vm.clear_script();
+    vm.set_step_limit(step_limit);
+
+    let mut verifier = verifier.borrow_mut();
+    if verifier.divergence.is_none() && verifier.position < verifier.expected.len() {
+        verifier.divergence = Some(Divergence {
+            index: verifier.position,
+            expected: verifier.expected.get(verifier.position).copied(),
+            actual: None,
+        });
+    }
+    (outcome, verifier.divergence.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::{Capture, LineReader};
+    use std::io::{empty, Cursor};
+
+    // 0: in r0; 2: eq r1 r0 'q'; 6: jt r1 13; 9: out r0; 11: jmp 0; 13: halt
+    fn echo_until_q(output: &Capture) -> VirtualMachine {
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0])
+            .expect("The binary should load without errors");
+        vm.set_output(output.clone());
+        vm
+    }
+
+    fn record(input: &str) -> Recording {
+        let capture = Capture::default();
+        let mut vm = echo_until_q(&Capture::default());
+        vm.set_input(LineReader::new(Cursor::new(input.to_string())));
+        let recorder = Rc::new(RefCell::new(Recorder::new(capture.clone()).expect("The header must be written")));
+        vm.add_observer(recorder.clone());
+        run_headless(&mut vm);
+        recorder.borrow_mut().flush().expect("The recording must be flushed");
+
+        Recording::read(Cursor::new(capture.text())).expect("The recording must be read")
+    }
+
+    #[test]
+    fn test_recording() {
+        let recording = record("ab\nq\n");
+
+        assert_eq!(&recording.events()[..3], [
+            Event { step: 0, direction: Direction::Input, c: b'a' },
+            Event { step: 3, direction: Direction::Output, c: b'a' },
+            Event { step: 5, direction: Direction::Input, c: b'b' },
+        ]);
+        assert_eq!(recording.input_lines(), ["ab", "q"]);
+        assert!(matches!(Recording::read(Cursor::new("> 1")), Err(RecordingError::InvalidFormat(1))));
+    }
+
+    #[test]
+    fn test_replay() {
+        let recording = record("ab\nq\n");
+        let output = Capture::default();
+        let mut vm = echo_until_q(&output);
+        vm.set_input(LineReader::new(empty()));
+
+        let (outcome, divergence) = replay(&mut vm, &recording, None);
+        assert!(matches!(outcome, Outcome::Halted));
+        assert_eq!(divergence, None);
+        assert_eq!(output.text(), "ab\n");
+    }
+
+    #[test]
+    fn test_replay_until() {
+        let recording = record("ab\nq\n");
+        let mut vm = echo_until_q(&Capture::default());
+        vm.set_input(LineReader::new(empty()));
+
+        let (outcome, divergence) = replay(&mut vm, &recording, Some(5));
+        assert!(matches!(outcome, Outcome::StepLimit));
+        assert_eq!(divergence, None);
+        assert_eq!(vm.steps(), 5);
+        assert_eq!(vm.pending_script(), 0);
+        assert_eq!(vm.step_limit(), None);
+    }
+
+    #[test]
+    fn test_replay_step_limit() {
+        let recording = record("ab\nq\n");
+        let mut vm = echo_until_q(&Capture::default());
+        vm.set_input(LineReader::new(empty()));
+        vm.set_step_limit(Some(4));
+
+        let (outcome, divergence) = replay(&mut vm, &recording, Some(8));
+        assert!(matches!(outcome, Outcome::StepLimit));
+        assert_eq!(divergence, None);
+        assert_eq!(vm.steps(), 4);
+        assert_eq!(vm.step_limit(), Some(4));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let mut recording = record("ab\nq\n");
+        recording.events[1].c = b'x';
+        let mut vm = echo_until_q(&Capture::default());
+        vm.set_input(LineReader::new(empty()));
+
+        let (_, divergence) = replay(&mut vm, &recording, None);
+        assert_eq!(divergence, Some(Divergence {
+            index: 1,
+            expected: Some(Event { step: 3, direction: Direction::Output, c: b'x' }),
+            actual: Some(Event { step: 3, direction: Direction::Output, c: b'a' }),
+        }));
+    }
+}
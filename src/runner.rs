@@ -0,0 +1,240 @@
+//! Runs a program without the debugger, e.g. in shell pipelines and golden tests.
+
+use crate::terminal::Capture;
+use crate::vm::{Stop, VirtualMachine, VirtualMachineError};
+use std::fmt;
+
+/// The number of lines shown before the first difference of a golden transcript.
+pub const CONTEXT_LINES: usize = 3;
+
+/// How a headless run has ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The program has executed `halt`.
+    Halted,
+    /// The program couldn't be executed any further.
+    Faulted(VirtualMachineError),
+    /// The program has executed the maximum number of instructions.
+    StepLimit,
+    /// The program waits for input, but the input has ended.
+    EndOfInput,
+}
+
+impl Outcome {
+    /// The exit code of the process: 0 - halted, 1 - faulted, 2 - step limit, 3 - end of input.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Halted => 0,
+            Outcome::Faulted(_) => 1,
+            Outcome::StepLimit => 2,
+            Outcome::EndOfInput => 3,
+        }
+    }
+}
+
+/// Runs the program until it halts, faults, reaches the step limit or runs out of input.
+///
+/// The breakpoints and the host commands are ignored, the output is flushed before returning.
+pub fn run_headless(vm: &mut VirtualMachine) -> Outcome {
+    let outcome = loop {
+        match vm.resume() {
+            Ok(None) | Ok(Some(Stop::Breakpoint(_))) => continue,
+            Ok(Some(Stop::Halted)) => break Outcome::Halted,
+            Ok(Some(Stop::StepLimit)) => break Outcome::StepLimit,
+            Ok(Some(Stop::EndOfInput)) | Ok(Some(Stop::Interrupted)) => break Outcome::EndOfInput,
+            Ok(Some(Stop::HostCommand(command))) => eprintln!("The host command '{}' is ignored without the debugger", command),
+            Err(err) => break Outcome::Faulted(err),
+        }
+    };
+
+    let _ = vm.flush_output();
+    outcome
+}
+
+/// The first line where the program output differs from the golden transcript.
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// The line number, starting from 1.
+    pub line: usize,
+    /// The expected line with its line ending, `None` if the output is longer than the transcript.
+    pub expected: Option<String>,
+    /// The actual line with its line ending, `None` if the output is shorter than the transcript.
+    pub actual: Option<String>,
+    /// The equal lines preceding the difference.
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "The output differs from the transcript at line {}:", self.line)?;
+        let first = self.line - self.context.len();
+        for (offset, line) in self.context.iter().enumerate() {
+            writeln!(f, "  {:>5} | {}", first + offset, line)?;
+        }
+        writeln!(f, "- {:>5} | {}", self.line, self.expected.as_deref().map_or(String::from("<end of transcript>"), show))?;
+        write!(f, "+ {:>5} | {}", self.line, self.actual.as_deref().map_or(String::from("<end of output>"), show))
+    }
+}
+
+// The line without the newline, a carriage return and a missing newline are made visible
+fn show(line: &str) -> String {
+    match line.strip_suffix('\n') {
+        Some(line) => line.replace('\r', "\\r"),
+        None => format!("{} <no newline>", line.replace('\r', "\\r")),
+    }
+}
+
+/// Compares the output line by line, returns the first difference.
+///
+/// The line endings are compared as well: a carriage return or a missing newline at the end is
+/// a difference.
+pub fn compare_transcript(expected: &str, actual: &str) -> Option<Mismatch> {
+    let mut expected_lines = expected.split_inclusive('\n');
+    let mut actual_lines = actual.split_inclusive('\n');
+    let mut context = Vec::new();
+
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (Some(expected), Some(actual)) if expected == actual => {
+                context.push(actual.trim_end_matches('\n').to_string());
+                if context.len() > CONTEXT_LINES {
+                    context.remove(0);
+                }
+            }
+            (expected, actual) => return Some(Mismatch {
+                line,
+                expected: expected.map(String::from),
+                actual: actual.map(String::from),
+                context,
+            }),
+        }
+    }
+
+    None
+}
+
+/// Runs the program headlessly and compares its output with the golden transcript.
+///
+/// The input source of the VM must be set up beforehand, the output is copied by the runner and
+/// still goes to the destination of the VM.
+pub fn run_golden(vm: &mut VirtualMachine, expected: &str) -> (Outcome, Option<Mismatch>) {
+    let capture = Capture::default();
+    vm.tee_output(capture.clone());
+
+    let outcome = run_headless(vm);
+    (outcome, compare_transcript(expected, &capture.text()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::LineReader;
+    use std::io::Cursor;
+
+    // 0: in r0; 2: eq r1 r0 'q'; 6: jt r1 13; 9: out r0; 11: jmp 0; 13: halt
+    fn echo_until_q(input: &str, output: &Capture) -> VirtualMachine {
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0])
+            .expect("The binary should load without errors");
+        vm.set_input(LineReader::new(Cursor::new(input.to_string())));
+        vm.set_output(output.clone());
+        vm
+    }
+
+    #[test]
+    fn test_halted() {
+        let output = Capture::default();
+        let mut vm = echo_until_q("ab\nq\n", &output);
+        vm.add_breakpoint(9);
+
+        let outcome = run_headless(&mut vm);
+        assert!(matches!(outcome, Outcome::Halted));
+        assert_eq!(outcome.exit_code(), 0);
+        assert_eq!(output.contents(), b"ab\n");
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        let output = Capture::default();
+        let mut vm = echo_until_q("abc", &output);
+
+        let outcome = run_headless(&mut vm);
+        assert!(matches!(outcome, Outcome::EndOfInput));
+        assert_eq!(outcome.exit_code(), 3);
+        assert_eq!(output.contents(), b"abc\n");
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let output = Capture::default();
+        let mut vm = echo_until_q("abc", &output);
+        vm.set_step_limit(Some(10));
+
+        let outcome = run_headless(&mut vm);
+        assert!(matches!(outcome, Outcome::StepLimit));
+        assert_eq!(outcome.exit_code(), 2);
+    }
+
+    #[test]
+    fn test_faulted() {
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![22]).expect("The binary should load without errors");
+
+        let outcome = run_headless(&mut vm);
+        assert!(matches!(outcome, Outcome::Faulted(_)));
+        assert_eq!(outcome.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_compare_transcript() {
+        assert_eq!(compare_transcript("a\nb\n", "a\nb\n"), None);
+
+        let mismatch = compare_transcript("1\n2\n3\n4\n5\n6\n", "1\n2\n3\n4\nfive\n6\n")
+            .expect("The transcripts differ");
+        assert_eq!(mismatch, Mismatch {
+            line: 5,
+            expected: Some(String::from("5\n")),
+            actual: Some(String::from("five\n")),
+            context: vec![String::from("2"), String::from("3"), String::from("4")],
+        });
+        assert_eq!(mismatch.to_string(), [
+            "The output differs from the transcript at line 5:",
+            "      2 | 2",
+            "      3 | 3",
+            "      4 | 4",
+            "-     5 | 5",
+            "+     5 | five",
+        ].join("\n"));
+
+        let mismatch = compare_transcript("1\n", "1\n2\n").expect("The output is longer");
+        assert_eq!(mismatch.expected, None);
+        assert_eq!(mismatch.actual, Some(String::from("2\n")));
+    }
+
+    #[test]
+    fn test_compare_line_endings() {
+        let mismatch = compare_transcript("a\nb\n", "a\nb").expect("The newline at the end is missing");
+        assert_eq!((mismatch.line, mismatch.actual.as_deref()), (2, Some("b")));
+        assert!(mismatch.to_string().ends_with("+     2 | b <no newline>"));
+
+        let mismatch = compare_transcript("a\nb\n", "a\r\nb\r\n").expect("The line endings differ");
+        assert_eq!(mismatch.line, 1);
+        assert!(mismatch.to_string().ends_with("-     1 | a\n+     1 | a\\r"));
+    }
+
+    #[test]
+    fn test_run_golden() {
+        let output = Capture::default();
+        let mut vm = echo_until_q("abc\nq\n", &output);
+
+        let (outcome, mismatch) = run_golden(&mut vm, "abc\n");
+        assert!(matches!(outcome, Outcome::Halted));
+        assert_eq!(mismatch, None);
+        assert_eq!(output.text(), "abc\n");
+
+        let mut vm = echo_until_q("abd\nq\n", &output);
+        let (_, mismatch) = run_golden(&mut vm, "abc\n");
+        assert_eq!(mismatch.map(|mismatch| mismatch.line), Some(1));
+    }
+}
@@ -0,0 +1,353 @@
+//! Named save slots: the snapshots in a directory with the metadata to find the right one.
+//!
+//! Every slot is a snapshot, `<name>.snap`, and a text file with its metadata, `<name>.meta`:
+//!
+//! ```text
+//! steps=512418
+//! time=1792324799123
+//! room=Foothills
+//! tail=You find yourself standing at the base of an enormous mountain.
+//! tail=What do I do?
+//! ```
+
+use crate::adventure::room_title;
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::vm::{Observer, VirtualMachine, VirtualMachineError};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The directory of the save slots, relative to the working directory.
+pub const DEFAULT_DIRECTORY: &str = "saves";
+/// The number of the last non-empty output lines kept with a slot.
+pub const TAIL_LINES: usize = 5;
+
+const SNAPSHOT_EXTENSION: &str = "snap";
+const METADATA_EXTENSION: &str = "meta";
+
+/// Errors reported by the [`SaveSlots`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SlotError {
+    Io(io::Error),
+    Snapshot(SnapshotError),
+    /// The snapshot couldn't be restored.
+    VirtualMachine(VirtualMachineError),
+    /// The name is empty or contains characters other than letters, digits, `-`, `_` and `.`.
+    InvalidName(String),
+    NotFound(String),
+    AlreadyExists(String),
+}
+
+/// The metadata of a save slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotInfo {
+    pub name: String,
+    /// The number of instructions executed before the slot was saved.
+    pub steps: u64,
+    /// When the slot was saved.
+    pub time: SystemTime,
+    /// The last room the game has described.
+    pub room: Option<String>,
+    /// The last non-empty output lines.
+    pub tail: Vec<String>,
+}
+
+/// Keeps the last room title and the last output lines, it observes the [`VirtualMachine`].
+#[derive(Debug, Default)]
+pub struct RecentOutput {
+    lines: VecDeque<String>,
+    line: Vec<u8>,
+    room: Option<String>,
+}
+
+impl RecentOutput {
+    pub fn room(&self) -> Option<&str> {
+        self.room.as_deref()
+    }
+
+    /// Returns the last non-empty output lines, the unfinished line included.
+    pub fn tail(&self) -> Vec<String> {
+        let line = String::from_utf8_lossy(&self.line);
+        let mut tail: Vec<String> = self.lines.iter().cloned().collect();
+        if !line.trim().is_empty() {
+            tail.push(line.trim_end().to_string());
+        }
+
+        tail.split_off(tail.len().saturating_sub(TAIL_LINES))
+    }
+
+    /// Continues from the room and the output of a loaded slot.
+    pub fn restore(&mut self, info: &SlotInfo) {
+        self.lines = info.tail.iter().cloned().collect();
+        self.line.clear();
+        self.room = info.room.clone();
+    }
+}
+
+impl Observer for RecentOutput {
+    fn output(&mut self, _step: u64, c: u8) {
+        if c != b'\n' {
+            self.line.push(c);
+            return;
+        }
+
+        let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+        self.line.clear();
+        if let Some(title) = room_title(&line) {
+            self.room = Some(title.to_string());
+        }
+        if !line.is_empty() {
+            self.lines.push_back(line);
+            if self.lines.len() > TAIL_LINES {
+                self.lines.pop_front();
+            }
+        }
+    }
+}
+
+/// Manages the save slots stored in a directory.
+#[derive(Debug, Clone)]
+pub struct SaveSlots {
+    directory: PathBuf,
+}
+
+impl Default for SaveSlots {
+    fn default() -> Self {
+        SaveSlots::new(DEFAULT_DIRECTORY)
+    }
+}
+
+impl SaveSlots {
+    /// Uses the directory, it is created with the first slot.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> SaveSlots {
+        SaveSlots {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Saves the state of the VM, the slot with the same name is overwritten.
+    pub fn save(&self, name: &str, vm: &VirtualMachine, recent: &RecentOutput) -> Result<SlotInfo, SlotError> {
+        check_name(name)?;
+        fs::create_dir_all(&self.directory).map_err(SlotError::Io)?;
+
+        let info = SlotInfo {
+            name: name.to_string(),
+            steps: vm.steps(),
+            time: SystemTime::now(),
+            room: recent.room().map(String::from),
+            tail: recent.tail(),
+        };
+        vm.snapshot().save_to_file(self.path(name, SNAPSHOT_EXTENSION)).map_err(SlotError::Snapshot)?;
+        fs::write(self.path(name, METADATA_EXTENSION), format_metadata(&info)).map_err(SlotError::Io)?;
+
+        Ok(info)
+    }
+
+    /// Restores the state of the VM from the slot.
+    pub fn load(&self, name: &str, vm: &mut VirtualMachine) -> Result<SlotInfo, SlotError> {
+        let info = self.info(name)?;
+        let snapshot = Snapshot::load_from_file(self.path(name, SNAPSHOT_EXTENSION)).map_err(SlotError::Snapshot)?;
+        vm.restore(&snapshot).map_err(SlotError::VirtualMachine)?;
+
+        Ok(info)
+    }
+
+    /// Reads the metadata of the slot.
+    pub fn info(&self, name: &str) -> Result<SlotInfo, SlotError> {
+        check_name(name)?;
+        match fs::read_to_string(self.path(name, METADATA_EXTENSION)) {
+            Ok(text) => Ok(parse_metadata(name, &text)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(SlotError::NotFound(name.to_string())),
+            Err(err) => Err(SlotError::Io(err)),
+        }
+    }
+
+    /// Returns the slots, the most recent first; the slots that cannot be read are skipped with a
+    /// warning.
+    pub fn list(&self) -> Result<Vec<SlotInfo>, SlotError> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(SlotError::Io(err)),
+        };
+
+        let mut slots = Vec::new();
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    eprintln!("Couldn't read an entry of '{}': {}", self.directory.display(), err);
+                    continue;
+                }
+            };
+            if path.extension().is_some_and(|extension| extension == METADATA_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    match self.info(name) {
+                        Ok(info) => slots.push(info),
+                        Err(err) => eprintln!("Skipped the slot '{}': {:?}", path.display(), err),
+                    }
+                }
+            }
+        }
+
+        slots.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+        Ok(slots)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), SlotError> {
+        self.info(name)?;
+        fs::remove_file(self.path(name, METADATA_EXTENSION)).map_err(SlotError::Io)?;
+        match fs::remove_file(self.path(name, SNAPSHOT_EXTENSION)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(SlotError::Io(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Renames the slot, an existing slot isn't overwritten.
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), SlotError> {
+        let mut info = self.info(from)?;
+        check_name(to)?;
+        if self.path(to, METADATA_EXTENSION).exists() {
+            return Err(SlotError::AlreadyExists(to.to_string()));
+        }
+
+        fs::rename(self.path(from, SNAPSHOT_EXTENSION), self.path(to, SNAPSHOT_EXTENSION)).map_err(SlotError::Io)?;
+        info.name = to.to_string();
+        fs::write(self.path(to, METADATA_EXTENSION), format_metadata(&info)).map_err(SlotError::Io)?;
+        fs::remove_file(self.path(from, METADATA_EXTENSION)).map_err(SlotError::Io)
+    }
+
+    fn path(&self, name: &str, extension: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", name, extension))
+    }
+}
+
+fn check_name(name: &str) -> Result<(), SlotError> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if name.is_empty() || name.starts_with('.') || !valid {
+        return Err(SlotError::InvalidName(name.to_string()));
+    }
+
+    Ok(())
+}
+
+fn format_metadata(info: &SlotInfo) -> String {
+    let millis = info.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut text = format!("steps={}\ntime={}\n", info.steps, millis);
+    if let Some(room) = &info.room {
+        text.push_str(&format!("room={}\n", room));
+    }
+    for line in &info.tail {
+        text.push_str(&format!("tail={}\n", line));
+    }
+
+    text
+}
+
+// The unknown keys and the invalid values are skipped, so older slots stay readable
+fn parse_metadata(name: &str, text: &str) -> SlotInfo {
+    let mut info = SlotInfo {
+        name: name.to_string(),
+        steps: 0,
+        time: UNIX_EPOCH,
+        room: None,
+        tail: Vec::new(),
+    };
+
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "steps" => info.steps = value.parse().unwrap_or_default(),
+            "time" => info.time = UNIX_EPOCH + Duration::from_millis(value.parse().unwrap_or_default()),
+            "room" => info.room = Some(value.to_string()),
+            "tail" => info.tail.push(value.to_string()),
+            _ => {}
+        }
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_slots(test: &str) -> SaveSlots {
+        let directory = env::temp_dir().join(format!("synacor-vm-slots-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        SaveSlots::new(directory)
+    }
+
+    fn recent(text: &str) -> RecentOutput {
+        let mut recent = RecentOutput::default();
+        text.bytes().for_each(|c| recent.output(0, c));
+        recent
+    }
+
+    #[test]
+    fn test_recent_output() {
+        let recent = recent("== Foothills ==\nYou are here.\n\n1\n2\n3\n4\nWhat do I do?");
+
+        assert_eq!(recent.room(), Some("Foothills"));
+        assert_eq!(recent.tail(), ["1", "2", "3", "4", "What do I do?"]);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let slots = temp_slots("save-load");
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![21, 21, 0]).expect("The binary should load without errors");
+        vm.step(1).expect("The program must not fail");
+
+        let saved = slots.save("vault", &vm, &recent("== Vault ==\nWhat do I do?\n"))
+            .expect("The slot must be saved");
+        assert_eq!(saved.steps, 1);
+        assert_eq!(saved.room.as_deref(), Some("Vault"));
+
+        vm.step(1).expect("The program must not fail");
+        let loaded = slots.load("vault", &mut vm).expect("The slot must be loaded");
+        assert_eq!(loaded.tail, ["== Vault ==", "What do I do?"]);
+        assert_eq!(loaded.time.duration_since(UNIX_EPOCH).unwrap().as_millis(),
+                   saved.time.duration_since(UNIX_EPOCH).unwrap().as_millis());
+        assert_eq!(vm.steps(), 1);
+        assert_eq!(vm.get_current_address(), 1);
+
+        let _ = fs::remove_dir_all(slots.directory());
+    }
+
+    #[test]
+    fn test_list_rename_delete() {
+        let slots = temp_slots("list");
+        let vm = VirtualMachine::default();
+        assert!(slots.list().expect("A missing directory has no slots").is_empty());
+
+        slots.save("first", &vm, &RecentOutput::default()).expect("The slot must be saved");
+        slots.save("second", &vm, &RecentOutput::default()).expect("The slot must be saved");
+        assert!(matches!(slots.rename("first", "second"), Err(SlotError::AlreadyExists(_))));
+        slots.rename("first", "third").expect("The slot must be renamed");
+        assert!(matches!(slots.info("first"), Err(SlotError::NotFound(_))));
+
+        let mut names: Vec<String> = slots.list().expect("The slots must be listed")
+            .into_iter().map(|info| info.name).collect();
+        names.sort();
+        assert_eq!(names, ["second", "third"]);
+
+        fs::write(slots.directory().join("bad name.meta"), "").expect("The file must be written");
+        fs::create_dir(slots.directory().join("broken.meta")).expect("The directory must be created");
+        assert_eq!(slots.list().expect("The unreadable slots must be skipped").len(), 2);
+
+        slots.delete("second").expect("The slot must be deleted");
+        assert_eq!(slots.list().expect("The slots must be listed").len(), 1);
+        assert!(matches!(slots.delete("second"), Err(SlotError::NotFound(_))));
+        assert!(matches!(slots.save("../escape", &vm, &RecentOutput::default()), Err(SlotError::InvalidName(_))));
+
+        let _ = fs::remove_dir_all(slots.directory());
+    }
+}
@@ -0,0 +1,272 @@
+//! The state of the whole VM that can be saved to a file and restored later.
+
+use crate::cpu::{Frame, MAX_REGISTERS};
+use crate::mem::MAX_ADDRESS;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SYNSNAP\0";
+const VERSION: u16 = 1;
+
+/// Errors reported while reading or writing a [`Snapshot`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// The file couldn't be read or written.
+    Io(io::Error),
+    /// The file is not a snapshot or was written by an unsupported version.
+    InvalidFormat,
+    /// The stack, the call frames or the pending input have more than `u32::MAX` elements.
+    TooLarge,
+}
+
+/// The memory, the registers, the stacks, the pending input and the program counter of the VM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub(crate) memory: Vec<u16>,
+    pub(crate) registers: [u16; MAX_REGISTERS],
+    pub(crate) stack: Vec<u16>,
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) input: Vec<u8>,
+    pub(crate) current_address: u16,
+    pub(crate) steps: u64,
+}
+
+impl Snapshot {
+    /// Returns the number of instructions executed before the snapshot was taken.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Returns the address of the next instruction to execute.
+    pub fn current_address(&self) -> u16 {
+        self.current_address
+    }
+
+    /// Writes the snapshot to a file, the file is overwritten.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Reads a snapshot from a file.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        Snapshot::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the snapshot in the little-endian binary format.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), SnapshotError> {
+        writer.write_all(MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_u16(writer, self.current_address)?;
+        writer.write_all(&self.steps.to_le_bytes())?;
+        write_words(writer, &self.registers)?;
+        write_words(writer, &self.memory)?;
+        write_words(writer, &self.stack)?;
+
+        write_count(writer, self.frames.len())?;
+        for frame in &self.frames {
+            write_words(writer, &[frame.call_site, frame.target])?;
+            write_words(writer, &frame.arguments)?;
+            writer.write_all(&(frame.depth as u64).to_le_bytes())?;
+        }
+
+        write_count(writer, self.input.len())?;
+        writer.write_all(&self.input)?;
+
+        Ok(())
+    }
+
+    /// Reads a snapshot written by [`Snapshot::write`].
+    pub fn read<R: Read>(reader: &mut R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u16(reader)? != VERSION {
+            return Err(SnapshotError::InvalidFormat);
+        }
+
+        let current_address = read_u16(reader)?;
+        let steps = read_u64(reader)?;
+        let mut registers = [0; MAX_REGISTERS];
+        let words = read_words(reader, MAX_REGISTERS)?;
+        if words.len() != MAX_REGISTERS {
+            return Err(SnapshotError::InvalidFormat);
+        }
+        registers.copy_from_slice(&words);
+        let memory = read_words(reader, MAX_ADDRESS)?;
+        if memory.len() != MAX_ADDRESS {
+            return Err(SnapshotError::InvalidFormat);
+        }
+        let stack = read_words(reader, usize::MAX)?;
+
+        let mut frames = Vec::new();
+        for _ in 0..read_count(reader)? {
+            let sites = read_words(reader, 2)?;
+            let arguments = read_words(reader, 3)?;
+            if sites.len() != 2 || arguments.len() != 3 {
+                return Err(SnapshotError::InvalidFormat);
+            }
+            frames.push(Frame {
+                call_site: sites[0],
+                target: sites[1],
+                arguments: [arguments[0], arguments[1], arguments[2]],
+                depth: read_u64(reader)? as usize,
+            });
+        }
+
+        let length = read_count(reader)?;
+        let mut input = Vec::new();
+        if reader.take(length as u64).read_to_end(&mut input)? != length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(Snapshot {
+            memory,
+            registers,
+            stack,
+            frames,
+            input,
+            current_address,
+            steps,
+        })
+    }
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+// The counts take 4 bytes because the memory takes 0x8000 words and the stack is unbounded
+fn write_count<W: Write>(writer: &mut W, count: usize) -> Result<(), SnapshotError> {
+    let count = u32::try_from(count).map_err(|_| SnapshotError::TooLarge)?;
+    writer.write_all(&count.to_le_bytes())?;
+
+    Ok(())
+}
+
+// The words are prefixed with their count
+fn write_words<W: Write>(writer: &mut W, words: &[u16]) -> Result<(), SnapshotError> {
+    write_count(writer, words.len())?;
+    words.iter().try_for_each(|&word| write_u16(writer, word))?;
+
+    Ok(())
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0_u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0_u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_count<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+// The words are not allocated in advance, a damaged count fails at the end of the file
+fn read_words<R: Read>(reader: &mut R, max_count: usize) -> Result<Vec<u16>, SnapshotError> {
+    let count = read_count(reader)?;
+    if count > max_count {
+        return Err(SnapshotError::InvalidFormat);
+    }
+
+    let mut words = Vec::new();
+    for _ in 0..count {
+        words.push(read_u16(reader)?);
+    }
+
+    Ok(words)
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample() -> Snapshot {
+        let mut memory = vec![0; MAX_ADDRESS];
+        memory[..4].copy_from_slice(&[21, 0, 17, 5]);
+        Snapshot {
+            memory,
+            registers: [1, 2, 3, 4, 5, 6, 7, 25734],
+            stack: vec![4, 1234],
+            frames: vec![Frame {
+                call_site: 2,
+                target: 5,
+                arguments: [1, 2, 3],
+                depth: 1,
+            }],
+            input: b"north\n".to_vec(),
+            current_address: 5,
+            steps: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn test_write_read() {
+        let snapshot = sample();
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).expect("The snapshot must be written");
+
+        let restored = Snapshot::read(&mut Cursor::new(buffer)).expect("The snapshot must be read");
+        assert_eq!(restored, snapshot);
+        assert_eq!(restored.steps(), 1_000_000);
+        assert_eq!(restored.current_address(), 5);
+    }
+
+    #[test]
+    fn test_large_stack() {
+        let mut snapshot = sample();
+        snapshot.stack = vec![7; MAX_ADDRESS + 1];
+        snapshot.input = vec![b'a'; 0x10000];
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).expect("The snapshot must be written");
+
+        let restored = Snapshot::read(&mut Cursor::new(buffer)).expect("The snapshot must be read");
+        assert_eq!(restored, snapshot);
+        assert!(matches!(write_count(&mut Vec::new(), u32::MAX as usize + 1), Err(SnapshotError::TooLarge)));
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        match Snapshot::read(&mut Cursor::new(b"NOTASNAPSHOT".to_vec())) {
+            Err(SnapshotError::InvalidFormat) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut buffer = Vec::new();
+        sample().write(&mut buffer).expect("The snapshot must be written");
+        buffer.truncate(buffer.len() - 1);
+        match Snapshot::read(&mut Cursor::new(buffer)) {
+            Err(SnapshotError::Io(_)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut snapshot = sample();
+        snapshot.memory.truncate(4);
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).expect("The snapshot must be written");
+        match Snapshot::read(&mut Cursor::new(buffer)) {
+            Err(SnapshotError::InvalidFormat) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/* Solution for the teleporter:
+The teleporter checks the eighth register with a confirmation routine, the routine at 0x178B,
+before it sends you to the real destination. It is an Ackermann-like function, modulo 32768:
+
+f(0, b) = b + 1
+f(a, 0) = f(a - 1, r7)
+f(a, b) = f(a - 1, f(a, b - 1))
+
+It is called with a = 4 and b = 1 and the result must be 6. Executed by the VM it recurses for
+ages, so it is computed natively here, row by row: f(a, ...) only needs the row f(a - 1, ...).
+
+Once the value is known, set r7 to it and skip the call to the routine before using the teleporter.
+ */
+
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::thread;
+
+const MODULO: usize = 32768;
+const A: usize = 4;
+const B: usize = 1;
+const EXPECTED_RESULT: u16 = 6;
+
+// Computes f(A, B) for the given eighth register, memoizing every row of the function
+fn confirm(r7: u16, rows: &mut [Vec<u16>; 2]) -> u16 {
+    let [previous, current] = rows;
+    previous.iter_mut().enumerate().for_each(|(b, value)| *value = ((b + 1) % MODULO) as u16);
+
+    for _ in 1..A {
+        current[0] = previous[r7 as usize];
+        for b in 1..MODULO {
+            current[b] = previous[current[b - 1] as usize];
+        }
+        std::mem::swap(previous, current);
+    }
+
+    (0..B).fold(previous[r7 as usize], |value, _| previous[value as usize])
+}
+
+fn main() {
+    let threads = thread::available_parallelism().map_or(4, |threads| threads.get());
+    let found = AtomicU16::new(0);
+
+    thread::scope(|scope| {
+        for first in 0..threads {
+            let found = &found;
+            scope.spawn(move || {
+                let mut rows = [vec![0; MODULO], vec![0; MODULO]];
+                for r7 in (1 + first..MODULO).step_by(threads) {
+                    if found.load(Ordering::Relaxed) != 0 {
+                        return;
+                    }
+                    if confirm(r7 as u16, &mut rows) == EXPECTED_RESULT {
+                        found.store(r7 as u16, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    match found.load(Ordering::Relaxed) {
+        0 => println!("Sorry, couldn't find an answer"),
+        r7 => println!("The answer is: {}. Set the eighth register to it and skip the confirmation", r7),
+    }
+}
@@ -0,0 +1,204 @@
+//! The game input and output of the VM.
+//!
+//! When the program executes `in` and the CPU has no characters left, the
+//! [`VirtualMachine`](crate::VirtualMachine) asks its [`InputSource`] for the next line.
+
+use crate::transcript::{is_transcript, read_transcript_input};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The line typed to break from the game back into the debugger: Ctrl-] and Enter, like telnet.
+pub const DEFAULT_ESCAPE: &str = "\x1d";
+
+/// The prefix of the lines typed during the game that are executed by the host, e.g. `!save vault`.
+pub const DEFAULT_HOST_PREFIX: &str = "!";
+
+/// The result of reading the game input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// A line for the program, with or without the trailing newline.
+    Line(String),
+    /// The user asked to pause the program and return to the debugger.
+    Interrupt,
+    /// There is no more input.
+    End,
+}
+
+/// Provides the lines consumed by the `in` instruction.
+pub trait InputSource {
+    fn read_line(&mut self) -> Input;
+}
+
+/// Reads the game input from the standard input, the escape line interrupts the program.
+///
+/// The standard input is shared with the debugger console, they take turns: the keyboard is read
+/// only while the program is running and waits for input.
+pub struct Keyboard {
+    escape: String,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new(DEFAULT_ESCAPE)
+    }
+}
+
+impl Keyboard {
+    pub fn new(escape: &str) -> Keyboard {
+        Keyboard {
+            escape: String::from(escape),
+        }
+    }
+}
+
+impl InputSource for Keyboard {
+    fn read_line(&mut self) -> Input {
+        let mut buffer = String::new();
+        match io::stdin().read_line(&mut buffer) {
+            Ok(size) if size > 0 => {
+                if buffer.trim_end_matches(&['\r', '\n'][..]) == self.escape {
+                    Input::Interrupt
+                } else {
+                    Input::Line(buffer)
+                }
+            }
+            _ => Input::End,
+        }
+    }
+}
+
+/// Reads the game input from a file, a pipe or any other buffered reader.
+pub struct LineReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader { reader }
+    }
+}
+
+impl<R: BufRead> InputSource for LineReader<R> {
+    fn read_line(&mut self) -> Input {
+        let mut buffer = String::new();
+        match self.reader.read_line(&mut buffer) {
+            Ok(size) if size > 0 => Input::Line(buffer),
+            _ => Input::End,
+        }
+    }
+}
+
+/// Reads the lines of a script, e.g. a walkthrough, without the line endings.
+pub fn read_script<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    reader.lines()
+        .map(|line| line.map(|line| line.trim_end_matches('\r').to_string()))
+        .collect()
+}
+
+/// Reads a script file, the input lines are extracted if the file is a session transcript.
+pub fn load_script<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)?;
+    if text.lines().next().is_some_and(is_transcript) {
+        read_transcript_input(text.as_bytes())
+    } else {
+        read_script(text.as_bytes())
+    }
+}
+
+/// Collects the program output in memory, the clones share the same buffer.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    /// Returns the collected output.
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+
+    /// Returns the collected output as text, the invalid UTF-8 sequences are replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    /// Clears the collected output.
+    pub fn clear(&self) {
+        self.buffer.borrow_mut().clear();
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the output to both writers, e.g. to the terminal and to a [`Capture`].
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Tee<A, B> {
+        Tee { first, second }
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_line_reader() {
+        let mut reader = LineReader::new(Cursor::new("take tablet\nuse tablet"));
+
+        assert_eq!(reader.read_line(), Input::Line(String::from("take tablet\n")));
+        assert_eq!(reader.read_line(), Input::Line(String::from("use tablet")));
+        assert_eq!(reader.read_line(), Input::End);
+    }
+
+    #[test]
+    fn test_read_script() {
+        let script = read_script(Cursor::new("doorway\r\nnorth\n\nnorth"))
+            .expect("The script must be read");
+
+        assert_eq!(script, ["doorway", "north", "", "north"]);
+    }
+
+    #[test]
+    fn test_capture() {
+        let capture = Capture::default();
+        let mut writer = capture.clone();
+
+        writer.write_all(b"== Foothills ==\n").expect("The capture must accept the output");
+        assert_eq!(capture.contents(), b"== Foothills ==\n");
+        assert_eq!(capture.text(), "== Foothills ==\n");
+
+        capture.clear();
+        assert!(capture.contents().is_empty());
+    }
+}
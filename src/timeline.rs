@@ -0,0 +1,212 @@
+//! The periodic checkpoints of the VM, used to travel back to any instruction of a long session.
+//!
+//! A checkpoint is taken every `interval` instructions. The oldest checkpoint keeps the whole
+//! memory, every other one keeps only the words changed since the previous checkpoint. The input
+//! lines fed to the program are kept as well, so the execution between two checkpoints can be
+//! repeated exactly, see [`VirtualMachine::rewind`](crate::VirtualMachine::rewind).
+
+use crate::snapshot::Snapshot;
+use std::collections::VecDeque;
+
+/// The number of instructions between two checkpoints by default.
+pub const DEFAULT_INTERVAL: u64 = 100_000;
+/// The number of checkpoints kept by default, the oldest ones are dropped.
+pub const DEFAULT_LENGTH: usize = 256;
+
+// A snapshot without the memory and the words changed since the previous checkpoint
+#[derive(Debug)]
+struct Checkpoint {
+    state: Snapshot,
+    changes: Vec<(u16, u16)>,
+}
+
+/// The ring of the periodic checkpoints and the input lines fed since the oldest one.
+#[derive(Debug)]
+pub struct Timeline {
+    interval: u64,
+    length: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    // The memory of the oldest and of the newest checkpoint
+    first_memory: Vec<u16>,
+    last_memory: Vec<u16>,
+    input: VecDeque<(u64, String)>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline::new(DEFAULT_INTERVAL, DEFAULT_LENGTH)
+    }
+}
+
+impl Timeline {
+    /// Creates an empty timeline, `interval` or `length` 0 disables the checkpoints.
+    pub fn new(interval: u64, length: usize) -> Timeline {
+        Timeline {
+            interval,
+            length,
+            checkpoints: VecDeque::new(),
+            first_memory: Vec::new(),
+            last_memory: Vec::new(),
+            input: VecDeque::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval > 0 && self.length > 0
+    }
+
+    /// Returns `true` if a checkpoint should be taken before executing the instruction `step`.
+    pub fn is_due(&self, step: u64) -> bool {
+        self.is_enabled() && step.is_multiple_of(self.interval)
+            && self.checkpoints.back().is_none_or(|last| last.state.steps < step)
+    }
+
+    /// Returns the steps of the checkpoints, the oldest first.
+    pub fn steps(&self) -> impl Iterator<Item=u64> + '_ {
+        self.checkpoints.iter().map(|checkpoint| checkpoint.state.steps)
+    }
+
+    /// Adds the checkpoint, the oldest one is dropped when the timeline is full.
+    pub(crate) fn push(&mut self, mut state: Snapshot) {
+        let memory = std::mem::take(&mut state.memory);
+        let changes = if self.checkpoints.is_empty() {
+            self.first_memory = memory.clone();
+            Vec::new()
+        } else {
+            changed_words(&self.last_memory, &memory)
+        };
+        self.last_memory = memory;
+        self.checkpoints.push_back(Checkpoint { state, changes });
+
+        if self.checkpoints.len() > self.length {
+            self.checkpoints.pop_front();
+            if let Some(first) = self.checkpoints.front_mut() {
+                apply_changes(&mut self.first_memory, &first.changes);
+                first.changes.clear();
+            }
+            let first_step = self.steps().next().unwrap_or_default();
+            while matches!(self.input.front(), Some((step, _)) if *step < first_step) {
+                self.input.pop_front();
+            }
+        }
+    }
+
+    /// Remembers the line fed to the program before executing the instruction `step`.
+    pub(crate) fn record_input(&mut self, step: u64, line: &str) {
+        if !self.checkpoints.is_empty() {
+            self.input.push_back((step, line.to_string()));
+        }
+    }
+
+    /// Returns the newest checkpoint taken at or before `step`.
+    pub fn checkpoint_before(&self, step: u64) -> Option<Snapshot> {
+        let index = self.checkpoints.iter().rposition(|checkpoint| checkpoint.state.steps <= step)?;
+        let mut memory = self.first_memory.clone();
+        self.checkpoints.iter().take(index + 1).for_each(|checkpoint| apply_changes(&mut memory, &checkpoint.changes));
+
+        let mut state = self.checkpoints[index].state.clone();
+        state.memory = memory;
+        Some(state)
+    }
+
+    /// Returns the input lines fed at or after `step`, with their steps.
+    pub fn input_since(&self, step: u64) -> impl Iterator<Item=&(u64, String)> + '_ {
+        self.input.iter().filter(move |(fed_at, _)| *fed_at >= step)
+    }
+
+    /// Forgets the checkpoints and the input after `step`, the execution has diverged from there.
+    pub(crate) fn truncate(&mut self, step: u64) {
+        while matches!(self.checkpoints.back(), Some(last) if last.state.steps > step) {
+            self.checkpoints.pop_back();
+        }
+        while matches!(self.input.back(), Some((fed_at, _)) if *fed_at >= step) {
+            self.input.pop_back();
+        }
+
+        match self.checkpoint_before(step) {
+            Some(state) => self.last_memory = state.memory,
+            None => self.clear(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.first_memory.clear();
+        self.last_memory.clear();
+        self.input.clear();
+    }
+}
+
+fn changed_words(before: &[u16], after: &[u16]) -> Vec<(u16, u16)> {
+    after.iter().enumerate()
+        .filter(|&(address, value)| before.get(address) != Some(value))
+        .map(|(address, &value)| (address as u16, value))
+        .collect()
+}
+
+fn apply_changes(memory: &mut Vec<u16>, changes: &[(u16, u16)]) {
+    for &(address, value) in changes {
+        let address = address as usize;
+        if address >= memory.len() {
+            memory.resize(address + 1, 0);
+        }
+        memory[address] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(steps: u64, memory: Vec<u16>) -> Snapshot {
+        Snapshot {
+            memory,
+            registers: [steps as u16; 8],
+            stack: Vec::new(),
+            frames: Vec::new(),
+            input: Vec::new(),
+            current_address: 0,
+            steps,
+        }
+    }
+
+    #[test]
+    fn test_deltas() {
+        let mut timeline = Timeline::new(10, 3);
+        assert!(timeline.is_due(0));
+        timeline.push(state(0, vec![1, 2, 3]));
+        assert!(!timeline.is_due(0));
+        assert!(!timeline.is_due(5));
+        timeline.push(state(10, vec![1, 5, 3]));
+        timeline.record_input(12, "north");
+        timeline.push(state(20, vec![1, 5, 7]));
+        assert_eq!(timeline.checkpoints[2].changes, [(2, 7)]);
+
+        timeline.push(state(30, vec![9, 5, 7]));
+        assert_eq!(timeline.steps().collect::<Vec<_>>(), [10, 20, 30]);
+        assert_eq!(timeline.first_memory, [1, 5, 3]);
+
+        let restored = timeline.checkpoint_before(29).expect("The checkpoint exists");
+        assert_eq!(restored.memory, [1, 5, 7]);
+        assert_eq!(restored.registers, [20; 8]);
+        assert_eq!(timeline.checkpoint_before(30).map(|state| state.memory), Some(vec![9, 5, 7]));
+        assert_eq!(timeline.checkpoint_before(9), None);
+        assert_eq!(timeline.input_since(10).collect::<Vec<_>>(), [&(12, String::from("north"))]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut timeline = Timeline::new(10, 4);
+        timeline.push(state(0, vec![1]));
+        timeline.push(state(10, vec![2]));
+        timeline.record_input(15, "north");
+        timeline.push(state(20, vec![3]));
+
+        timeline.truncate(15);
+        assert_eq!(timeline.steps().collect::<Vec<_>>(), [0, 10]);
+        assert_eq!(timeline.input_since(0).count(), 0);
+
+        timeline.push(state(20, vec![4]));
+        assert_eq!(timeline.checkpoint_before(20).map(|state| state.memory), Some(vec![4]));
+    }
+}
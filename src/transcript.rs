@@ -0,0 +1,198 @@
+//! The session transcript: the program output and input, line by line, with the time and the step.
+//!
+//! ```text
+//! # synacor-vm transcript
+//! 2026-10-18 12:00:00.123       512403 < What do I do?
+//! 2026-10-18 12:00:04.870       512418 > take tablet
+//! ```
+//!
+//! The input lines of a transcript can be replayed with [`read_transcript_input`].
+
+use crate::vm::Observer;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first line of every transcript.
+pub const HEADER: &str = "# synacor-vm transcript";
+
+const OUTPUT: char = '<';
+const INPUT: char = '>';
+// "YYYY-MM-DD hh:mm:ss.mmm "
+const TIMESTAMP_LENGTH: usize = 24;
+
+/// Writes the transcript of a session, it observes the [`VirtualMachine`](crate::VirtualMachine).
+pub struct Transcript<W: Write> {
+    writer: W,
+    // The direction, the step and the time of the first character and the characters of the unfinished line
+    line: Option<(char, u64, SystemTime, Vec<u8>)>,
+}
+
+impl Transcript<BufWriter<File>> {
+    /// Creates the transcript file, the file is overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Transcript::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Transcript<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Transcript {
+            writer,
+            line: None,
+        })
+    }
+
+    /// Writes the unfinished line.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some((direction, step, time, text)) = self.line.take() {
+            writeln!(self.writer, "{} {:>12} {} {}",
+                     format_timestamp(time), step, direction, String::from_utf8_lossy(&text))?;
+        }
+        self.writer.flush()
+    }
+
+    fn push(&mut self, direction: char, step: u64, c: u8) {
+        if !matches!(&self.line, Some((current, _, _, _)) if *current == direction) {
+            let _ = self.flush();
+        }
+
+        let line = self.line.get_or_insert_with(|| (direction, step, SystemTime::now(), Vec::new()));
+        if c == b'\n' {
+            let _ = self.flush();
+        } else {
+            line.3.push(c);
+        }
+    }
+}
+
+impl<W: Write> Observer for Transcript<W> {
+    fn output(&mut self, step: u64, c: u8) {
+        self.push(OUTPUT, step, c);
+    }
+
+    fn input(&mut self, step: u64, c: u8) {
+        self.push(INPUT, step, c);
+    }
+}
+
+impl<W: Write> Drop for Transcript<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Returns `true` if the first line is the transcript header.
+pub fn is_transcript(first_line: &str) -> bool {
+    first_line.trim_end() == HEADER
+}
+
+/// Extracts the input lines of a transcript, so the session can be replayed as a script.
+pub fn read_transcript_input<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    let mut input = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let entry = line.get(TIMESTAMP_LENGTH..).map(str::trim_start)
+            .and_then(|entry| entry.split_once(' '))
+            .map(|(_, entry)| entry);
+
+        if let Some(text) = entry.and_then(|entry| entry.strip_prefix(INPUT)) {
+            input.push(text.strip_prefix(' ').unwrap_or(text).to_string());
+        }
+    }
+
+    Ok(input)
+}
+
+/// Formats the time as `YYYY-MM-DD hh:mm:ss.mmm` in UTC.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            year, month, day,
+            seconds % 86_400 / 3600, seconds % 3600 / 60, seconds % 60,
+            since_epoch.subsec_millis())
+}
+
+// Converts the days since 1970-01-01 to the date, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Capture;
+    use std::io::Cursor;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00.000");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_millis(951_782_400_250)),
+                   "2000-02-29 00:00:00.250");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(1_792_324_799)),
+                   "2026-10-18 11:59:59.000");
+    }
+
+    #[test]
+    fn test_transcript() {
+        let capture = Capture::default();
+        {
+            let mut transcript = Transcript::new(capture.clone()).expect("The header must be written");
+            b"What do I do?\n".iter().for_each(|&c| transcript.output(10, c));
+            b"look\n".iter().for_each(|&c| transcript.input(20, c));
+            b"Prompt: ".iter().for_each(|&c| transcript.output(30, c));
+            b"north\n".iter().for_each(|&c| transcript.input(40, c));
+            b"tail".iter().for_each(|&c| transcript.output(50, c));
+        }
+
+        let text = capture.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], HEADER);
+        let entries: Vec<&str> = lines[1..].iter().map(|line| &line[TIMESTAMP_LENGTH..]).collect();
+        assert_eq!(entries, [
+            "          10 < What do I do?",
+            "          20 > look",
+            "          30 < Prompt: ",
+            "          40 > north",
+            "          50 < tail",
+        ]);
+
+        assert!(is_transcript(lines[0]));
+        let input = read_transcript_input(Cursor::new(text)).expect("The transcript must be read");
+        assert_eq!(input, ["look", "north"]);
+    }
+
+    #[test]
+    fn test_line_time() {
+        let capture = Capture::default();
+        let start = format_timestamp(SystemTime::now());
+        {
+            let mut transcript = Transcript::new(capture.clone()).expect("The header must be written");
+            transcript.output(10, b'a');
+            thread::sleep(Duration::from_millis(20));
+            transcript.output(11, b'\n');
+        }
+        let end = format_timestamp(SystemTime::now());
+
+        let text = capture.text();
+        let time = &text.lines().nth(1).expect("The line must be written")[..TIMESTAMP_LENGTH - 1];
+        assert!(start.as_str() <= time && time < end.as_str(), "{} <= {} < {}", start, time, end);
+    }
+}
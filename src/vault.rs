@@ -0,0 +1,199 @@
+/* Solution for the vault:
+The antechamber of the vault is a grid of numbers and operators, the orb starts at the bottom
+left corner and the door is at the top right corner:
+
+*  8  -  1
+4  * 11  *
++  4  -  18
+22 -  9  *
+
+Walking over an operator and then a number applies it to the weight of the orb, it must weigh 30
+when it reaches the door. The orb is reset when it is carried back to the start tile, it vanishes
+when it reaches the door with another weight, and the hourglass gives a limited number of steps.
+
+The grid, the start, the door, the target and the limit are read from a file, see test/vault.txt.
+The breadth-first search prints the shortest path as the game commands.
+ */
+
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::process::exit;
+
+const DIRECTIONS: [(&str, i32, i32); 4] = [("north", 0, -1), ("east", 1, 0), ("south", 0, 1), ("west", -1, 0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Tile {
+    Number(i64),
+    Add,
+    Subtract,
+    Multiply,
+}
+
+struct Vault {
+    grid: Vec<Vec<Tile>>,
+    start: (i32, i32),
+    door: (i32, i32),
+    target: i64,
+    limit: usize,
+}
+
+fn parse_tile(text: &str) -> Result<Tile, String> {
+    match text {
+        "+" => Ok(Tile::Add),
+        "-" => Ok(Tile::Subtract),
+        "*" => Ok(Tile::Multiply),
+        number => number.parse().map(Tile::Number).map_err(|_| format!("'{}' is not a tile", number)),
+    }
+}
+
+fn parse_numbers<T: std::str::FromStr>(key: &str, values: &[&str], count: usize) -> Result<Vec<T>, String> {
+    values.iter()
+        .map(|value| value.parse().map_err(|_| format!("'{}' expects numbers, got '{}'", key, value)))
+        .collect::<Result<Vec<T>, String>>()
+        .and_then(|numbers| if numbers.len() == count {
+            Ok(numbers)
+        } else {
+            Err(format!("'{}' expects {} number(s)", key, count))
+        })
+}
+
+fn parse_vault(text: &str) -> Result<Vault, String> {
+    let mut grid = Vec::new();
+    let (mut start, mut door, mut target, mut limit) = (None, None, None, None);
+    let mut in_grid = false;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if in_grid {
+            grid.push(words.iter().map(|word| parse_tile(word)).collect::<Result<Vec<Tile>, String>>()?);
+            continue;
+        }
+
+        match words[0] {
+            "target" => target = Some(parse_numbers::<i64>("target", &words[1..], 1)?[0]),
+            "limit" => limit = Some(parse_numbers::<usize>("limit", &words[1..], 1)?[0]),
+            "start" => start = parse_numbers::<i32>("start", &words[1..], 2).map(|xy| Some((xy[0], xy[1])))?,
+            "door" => door = parse_numbers::<i32>("door", &words[1..], 2).map(|xy| Some((xy[0], xy[1])))?,
+            "grid" => in_grid = true,
+            key => return Err(format!("Unknown key '{}'", key)),
+        }
+    }
+
+    let width = grid.first().map_or(0, Vec::len) as i32;
+    if width == 0 || grid.iter().any(|row| row.len() as i32 != width) {
+        return Err(String::from("The grid must be a non-empty rectangle"));
+    }
+    let height = grid.len() as i32;
+    let vault = Vault {
+        start: start.unwrap_or((0, height - 1)),
+        door: door.unwrap_or((width - 1, 0)),
+        target: target.ok_or("The target is missing")?,
+        limit: limit.ok_or("The limit is missing")?,
+        grid,
+    };
+
+    for (name, position) in [("start", vault.start), ("door", vault.door)] {
+        match vault.tile(position) {
+            Some(Tile::Number(_)) => {}
+            _ => return Err(format!("The {} must be a number on the grid", name)),
+        }
+    }
+
+    Ok(vault)
+}
+
+impl Vault {
+    fn tile(&self, (x, y): (i32, i32)) -> Option<Tile> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+        self.grid.get(y as usize).and_then(|row| row.get(x as usize)).copied()
+    }
+
+    // Returns the shortest list of moves that brings the orb with the target weight to the door
+    fn solve(&self) -> Option<Vec<&'static str>> {
+        let weight = match self.tile(self.start) {
+            Some(Tile::Number(weight)) => weight,
+            _ => return None,
+        };
+
+        // The position, the weight and the pending operator
+        type State = ((i32, i32), i64, Option<Tile>);
+        let initial: State = (self.start, weight, None);
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(initial);
+        queue.push_back((initial, Vec::new()));
+
+        while let Some(((position, weight, operator), path)) = queue.pop_front() {
+            if path.len() >= self.limit {
+                continue;
+            }
+
+            for &(direction, dx, dy) in &DIRECTIONS {
+                let next = (position.0 + dx, position.1 + dy);
+                if next == self.start {
+                    continue;
+                }
+
+                let state = match (self.tile(next), operator) {
+                    (None, _) => continue,
+                    (Some(Tile::Number(number)), Some(operator)) => match apply(operator, weight, number) {
+                        Some(weight) => (next, weight, None),
+                        None => continue,
+                    },
+                    (Some(Tile::Number(_)), None) => continue,
+                    (Some(operator), _) => (next, weight, Some(operator)),
+                };
+
+                let mut next_path = path.clone();
+                next_path.push(direction);
+                if next == self.door {
+                    if state.1 == self.target {
+                        return Some(next_path);
+                    }
+                    continue;
+                }
+
+                if visited.insert(state) {
+                    queue.push_back((state, next_path));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// `None` when the weight overflows, the path is dropped
+fn apply(operator: Tile, weight: i64, number: i64) -> Option<i64> {
+    match operator {
+        Tile::Add => weight.checked_add(number),
+        Tile::Subtract => weight.checked_sub(number),
+        Tile::Multiply => weight.checked_mul(number),
+        Tile::Number(_) => Some(weight),
+    }
+}
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("Usage: vault <FILE>, see test/vault.txt");
+        exit(64);
+    });
+    let vault = fs::read_to_string(&path)
+        .map_err(|err| format!("Couldn't read '{}': {}", path, err))
+        .and_then(|text| parse_vault(&text))
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(65);
+        });
+
+    match vault.solve() {
+        Some(moves) => {
+            println!("The answer is {} steps:", moves.len());
+            moves.iter().for_each(|direction| println!("{}", direction));
+        }
+        None => println!("Sorry, couldn't find an answer"),
+    }
+}
@@ -1,19 +1,86 @@
 use std::fs;
-use std::io::{ErrorKind};
+use std::io::{self, ErrorKind, Write};
 use crate::mem::{Memory, MemoryError};
-use crate::cpu::{CPU};
+use crate::cpu::{CPU, CPUError};
+use crate::patch::{Patch, PatchError};
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::terminal::{Input, InputSource, Keyboard, Tee};
+use crate::timeline::Timeline;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::iter::FromIterator;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
+/// The Synacor virtual machine: the memory and the CPU that executes it.
 pub struct VirtualMachine {
     memory: Rc<RefCell<Memory>>,
     pub cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+    hooks: BTreeMap<u16, Hook>,
+    input: Box<dyn InputSource>,
+    script: VecDeque<String>,
+    echo_script: bool,
+    host_prefix: Option<String>,
+    output: Box<dyn Write>,
+    observers: Vec<Rc<RefCell<dyn Observer>>>,
+    steps: u64,
+    step_limit: Option<u64>,
+    checkpoints: VecDeque<Snapshot>,
+    checkpoint_limit: usize,
+    timeline: Timeline,
 }
 
+/// A native replacement of the code at an address, see [`VirtualMachine::add_hook`].
+///
+/// The hook gets the CPU with the registers, the memory and the stack, and returns the address
+/// of the next instruction.
+pub type Hook = Box<dyn FnMut(&mut CPU) -> Result<u16, CPUError>>;
+
+/// The number of the game commands that the debugger can undo, see [`VirtualMachine::set_checkpoint_limit`].
+pub const DEFAULT_CHECKPOINTS: usize = 100;
+
+/// Receives the characters exchanged with the program, see [`VirtualMachine::add_observer`].
+///
+/// `step` is the number of instructions executed before the `in` or `out` instruction.
+pub trait Observer {
+    /// The program has written `c` with `out`.
+    fn output(&mut self, _step: u64, _c: u8) {}
+
+    /// The program has consumed `c` with `in`.
+    fn input(&mut self, _step: u64, _c: u8) {}
+}
+
+/// The reason why the VM cannot continue the execution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// The program has executed `halt` or `ret` with the empty stack.
+    Halted,
+    /// The program has reached a breakpoint.
+    Breakpoint(u16),
+    /// The user has broken into the debugger while the program was waiting for input.
+    Interrupted,
+    /// The program waits for input, but the input source is exhausted.
+    EndOfInput,
+    /// The program has executed the maximum number of instructions, see [`VirtualMachine::set_step_limit`].
+    StepLimit,
+    /// The input line starts with the host prefix, it contains the line without the prefix.
+    ///
+    /// The line isn't fed to the program, see [`VirtualMachine::set_host_prefix`].
+    HostCommand(String),
+}
+
+/// Errors reported by the [`VirtualMachine`].
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum VirtualMachineError {
+    /// The binary file doesn't exist, contains the path.
     CannotLoadFile(String),
+    /// The CPU couldn't execute the current instruction.
+    CPUFault(CPUError),
+    /// The snapshot couldn't be saved or loaded.
+    Snapshot(SnapshotError),
+    /// The step is in the future or before the oldest checkpoint of the timeline.
+    OutOfTimeline(u64),
     GeneralError,
 }
 
@@ -23,11 +90,25 @@ impl Default for VirtualMachine {
         VirtualMachine {
             memory: Rc::clone(&mem),
             cpu: CPU::new(Rc::clone(&mem)),
+            breakpoints: BTreeSet::new(),
+            hooks: BTreeMap::new(),
+            input: Box::new(Keyboard::default()),
+            script: VecDeque::new(),
+            echo_script: false,
+            host_prefix: None,
+            output: Box::new(io::stdout()),
+            observers: Vec::new(),
+            steps: 0,
+            step_limit: None,
+            checkpoints: VecDeque::new(),
+            checkpoint_limit: 0,
+            timeline: Timeline::new(0, 0),
         }
     }
 }
 
 impl VirtualMachine {
+    /// Loads the binary returned by `fn_get_binary` into the memory.
     pub fn load_binary<F>(&mut self, fn_get_binary: F) -> Result<(), VirtualMachineError>
         where F: FnOnce() -> Vec<u16> {
         let u16_binary = fn_get_binary();
@@ -37,43 +118,443 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// Applies the patch to the memory, returns the number of the changes written.
+    ///
+    /// See [`Patch::apply`], nothing is written if the memory doesn't contain the original words.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<usize, PatchError> {
+        patch.apply(&mut self.memory.borrow_mut())
+    }
+
+    /// Reads a binary file and converts it to the little-endian 16-bit words.
     pub fn get_binary_from_path(path: &str) -> Result<Vec<u16>, VirtualMachineError> {
-        fs::read(path).or_else(|err| {
+        fs::read(path).map_err(|err| {
             match err.kind() {
-                ErrorKind::NotFound => Err(VirtualMachineError::CannotLoadFile(String::from(path))),
-                _ => Err(VirtualMachineError::GeneralError),
+                ErrorKind::NotFound => VirtualMachineError::CannotLoadFile(String::from(path)),
+                _ => VirtualMachineError::GeneralError,
             }
-        }).and_then(|binary| {
-            Ok(binary_to_memory(&binary))
-        })
+        }).map(|binary| binary_to_memory(&binary))
+    }
+
+    /// Replaces the source of the game input, the keyboard by default.
+    pub fn set_input<I: InputSource + 'static>(&mut self, input: I) {
+        self.input = Box::new(input);
+    }
+
+    /// Queues the lines, e.g. a walkthrough, that `in` consumes before reading the input source.
+    pub fn add_script<I: IntoIterator<Item=String>>(&mut self, lines: I) {
+        self.script.extend(lines);
+    }
+
+    /// Returns the number of the script lines that are not consumed yet.
+    pub fn pending_script(&self) -> usize {
+        self.script.len()
+    }
+
+    /// Discards the script lines that are not consumed yet.
+    pub fn clear_script(&mut self) {
+        self.script.clear();
+    }
+
+    /// Enables or disables copying the script lines to the program output, as if they were typed.
+    pub fn set_script_echo(&mut self, echo: bool) {
+        self.echo_script = echo;
+    }
+
+    /// Sets the prefix of the input lines that are commands for the host instead of the program,
+    /// e.g. [`DEFAULT_HOST_PREFIX`](crate::terminal::DEFAULT_HOST_PREFIX); `None`, the default,
+    /// feeds every line to the program.
+    pub fn set_host_prefix(&mut self, prefix: Option<&str>) {
+        self.host_prefix = prefix.map(String::from);
+    }
+
+    /// Replaces the destination of the program output, the standard output by default.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Copies the program output to `copy`, the output goes to its destination as well.
+    pub fn tee_output<W: Write + 'static>(&mut self, copy: W) {
+        let output = std::mem::replace(&mut self.output, Box::new(io::sink()));
+        self.output = Box::new(Tee::new(output, copy));
+    }
+
+    /// Subscribes the observer to the program input and output, the caller may keep a clone of
+    /// the `Rc` to inspect the observer later.
+    pub fn add_observer(&mut self, observer: Rc<RefCell<dyn Observer>>) {
+        self.observers.push(observer);
+    }
+
+    /// Unsubscribes the observer added by [`VirtualMachine::add_observer`].
+    pub fn remove_observer(&mut self, observer: &Rc<RefCell<dyn Observer>>) {
+        self.observers.retain(|current| !Rc::ptr_eq(current, observer));
+    }
+
+    /// Flushes the program output.
+    pub fn flush_output(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    /// Returns the number of executed instructions.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Limits the total number of executed instructions, see [`Stop::StepLimit`].
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    pub fn step_limit(&self) -> Option<u64> {
+        self.step_limit
+    }
+
+    /// Captures the state of the VM, the breakpoints and the input/output aren't included.
+    pub fn snapshot(&self) -> Snapshot {
+        self.cpu.save_state(self.steps)
+    }
+
+    /// Restores the state captured by [`VirtualMachine::snapshot`], the timeline and the checkpoints
+    /// of [`VirtualMachine::undo`] are cleared.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), VirtualMachineError> {
+        self.restore_state(snapshot)?;
+        self.timeline.clear();
+        self.checkpoints.clear();
+
+        Ok(())
+    }
+
+    fn restore_state(&mut self, snapshot: &Snapshot) -> Result<(), VirtualMachineError> {
+        self.cpu.load_state(snapshot).map_err(VirtualMachineError::CPUFault)?;
+        self.steps = snapshot.steps();
+
+        Ok(())
+    }
+
+    /// Takes a checkpoint every `interval` instructions and keeps the last `length` ones,
+    /// see [`VirtualMachine::rewind`]; 0 disables the checkpoints, they are disabled by default.
+    pub fn set_timeline(&mut self, interval: u64, length: usize) {
+        self.timeline = Timeline::new(interval, length);
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Forgets the timeline, e.g. after the memory or the registers were changed by hand and the
+    /// execution cannot be repeated.
+    pub fn clear_timeline(&mut self) {
+        self.timeline.clear();
     }
 
-    pub fn next_step(&mut self) -> Result<bool, VirtualMachineError> {
-        match self.cpu.execute() {
-            Ok(to_stop) => Ok(to_stop),
-            Err(err) => {
-                eprintln!("CPU error {:?}", err);
-                Err(VirtualMachineError::GeneralError)
+    /// Travels back to the instruction `step`: restores the nearest checkpoint of the timeline
+    /// and executes the program again with the same input, the output isn't repeated.
+    ///
+    /// The observers aren't notified of the input and the output executed again, they have seen
+    /// them already.
+    ///
+    /// Returns the step of the restored checkpoint.
+    pub fn rewind(&mut self, step: u64) -> Result<u64, VirtualMachineError> {
+        let checkpoint = self.timeline.checkpoint_before(step)
+            .filter(|_| step <= self.steps)
+            .ok_or(VirtualMachineError::OutOfTimeline(step))?;
+        let mut input: VecDeque<(u64, String)> = self.timeline.input_since(checkpoint.steps()).cloned().collect();
+        self.restore_state(&checkpoint)?;
+
+        while self.steps < step {
+            if self.needs_input() {
+                match input.pop_front() {
+                    Some((fed_at, line)) if fed_at == self.steps => {
+                        self.cpu.feed_input(line.as_bytes());
+                        if !line.ends_with('\n') {
+                            self.cpu.feed_input(b"\n");
+                        }
+                    }
+                    _ => return Err(VirtualMachineError::OutOfTimeline(step)),
+                }
+            }
+
+            let result = self.execute_instruction();
+            self.steps += 1;
+            self.cpu.take_output();
+            match result {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(err) => return Err(VirtualMachineError::CPUFault(err)),
             }
         }
+
+        self.timeline.truncate(self.steps);
+        let steps = self.steps;
+        self.checkpoints.retain(|checkpoint| checkpoint.steps() < steps);
+
+        Ok(checkpoint.steps())
     }
 
-    pub fn run(&mut self) {
-        while let Ok(to_stop) = self.next_step() {
-            if to_stop {
-                break;
+    /// Limits the number of the checkpoints, 0 (the default) disables them, see [`VirtualMachine::undo`].
+    pub fn set_checkpoint_limit(&mut self, limit: usize) {
+        self.checkpoint_limit = limit;
+        while self.checkpoints.len() > limit {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// Returns the number of the game commands that can be undone.
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Restores the state from before the last `count` input lines, returns the number of the
+    /// undone lines: fewer than `count` if there are not enough checkpoints.
+    ///
+    /// A checkpoint is taken every time the program starts reading a new line.
+    pub fn undo(&mut self, count: usize) -> Result<usize, VirtualMachineError> {
+        let count = count.min(self.checkpoints.len());
+        if count == 0 {
+            return Ok(0);
+        }
+
+        self.checkpoints.truncate(self.checkpoints.len() - count + 1);
+        let checkpoint = self.checkpoints.pop_back().expect("The checkpoint exists");
+        self.restore_state(&checkpoint)?;
+        self.timeline.truncate(checkpoint.steps());
+
+        Ok(count)
+    }
+
+    /// Saves the state of the VM to a file.
+    pub fn save_snapshot(&self, path: &str) -> Result<(), VirtualMachineError> {
+        self.snapshot().save_to_file(path).map_err(VirtualMachineError::Snapshot)
+    }
+
+    /// Restores the state of the VM from a file written by [`VirtualMachine::save_snapshot`].
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), VirtualMachineError> {
+        let snapshot = Snapshot::load_from_file(path).map_err(VirtualMachineError::Snapshot)?;
+        self.restore(&snapshot)
+    }
+
+    /// Executes a single instruction, returns the reason if the program cannot continue.
+    ///
+    /// If the instruction is `in` and the CPU has no input, a line is read from the input source
+    /// first. The instruction isn't executed when the input is interrupted or exhausted.
+    pub fn next_step(&mut self) -> Result<Option<Stop>, VirtualMachineError> {
+        if matches!(self.step_limit, Some(limit) if self.steps >= limit) {
+            return Ok(Some(Stop::StepLimit));
+        }
+        if self.timeline.is_due(self.steps) {
+            self.timeline.push(self.snapshot());
+        }
+
+        if self.needs_input() {
+            let _ = self.output.flush();
+            match self.read_input() {
+                Input::Line(line) => {
+                    if let Some(command) = self.host_command(&line) {
+                        return Ok(Some(Stop::HostCommand(command)));
+                    }
+                    self.checkpoint();
+                    self.timeline.record_input(self.steps, &line);
+                    self.cpu.feed_input(line.as_bytes());
+                    if !line.ends_with('\n') {
+                        self.cpu.feed_input(b"\n");
+                    }
+                }
+                Input::Interrupt => return Ok(Some(Stop::Interrupted)),
+                Input::End => return Ok(Some(Stop::EndOfInput)),
+            }
+        }
+
+        let pending_input = self.cpu.pending_input();
+        let next_input = self.cpu.next_input();
+        let result = self.execute_instruction();
+        let step = self.steps;
+        self.steps += 1;
+
+        if let Some(c) = next_input.filter(|_| self.cpu.pending_input() < pending_input) {
+            self.observers.iter().for_each(|observer| observer.borrow_mut().input(step, c));
+        }
+        let output = self.cpu.take_output();
+        if !output.is_empty() {
+            let _ = self.output.write_all(&output);
+            for &c in &output {
+                self.observers.iter().for_each(|observer| observer.borrow_mut().output(step, c));
             }
         }
+
+        match result {
+            Ok(to_stop) if to_stop => Ok(Some(Stop::Halted)),
+            Ok(_) => Ok(None),
+            Err(err) => Err(VirtualMachineError::CPUFault(err)),
+        }
     }
 
-    pub fn run_until(&mut self, at: u16) {
-        while let Ok(to_stop) = self.next_step() {
-            if to_stop || self.get_current_address() >= at {
-                break;
+    // Executes the instruction at the program counter or the hook that replaces it
+    fn execute_instruction(&mut self) -> Result<bool, CPUError> {
+        match self.hooks.get_mut(&self.cpu.get_current_address()) {
+            Some(hook) => hook(&mut self.cpu).map(|address| {
+                self.cpu.set_current_address(address);
+                false
+            }),
+            None => self.cpu.execute(),
+        }
+    }
+
+    // The hooks don't read the input, even if they replace an `in` instruction
+    fn needs_input(&self) -> bool {
+        !self.hooks.contains_key(&self.cpu.get_current_address()) && self.cpu.needs_input()
+    }
+
+    fn checkpoint(&mut self) {
+        if self.checkpoint_limit > 0 {
+            if self.checkpoints.len() >= self.checkpoint_limit {
+                self.checkpoints.pop_front();
             }
+            self.checkpoints.push_back(self.snapshot());
         }
     }
 
+    fn host_command(&self, line: &str) -> Option<String> {
+        let prefix = self.host_prefix.as_ref()?;
+        line.trim_start().strip_prefix(prefix.as_str()).map(|command| command.trim().to_string())
+    }
+
+    // Takes the next line of the script, the input source is read when the script is over
+    fn read_input(&mut self) -> Input {
+        match self.script.pop_front() {
+            Some(line) => {
+                if self.echo_script {
+                    let _ = self.output.write_all(line.as_bytes());
+                    if !line.ends_with('\n') {
+                        let _ = self.output.write_all(b"\n");
+                    }
+                }
+                Input::Line(line)
+            }
+            None => self.input.read_line(),
+        }
+    }
+
+    /// Executes up to `count` instructions, returns the reason if the program cannot continue; it
+    /// stops at a breakpoint reached before the last instruction.
+    pub fn step(&mut self, count: usize) -> Result<Option<Stop>, VirtualMachineError> {
+        for executed in 1..=count {
+            if let Some(stop) = self.next_step()? {
+                return Ok(Some(stop));
+            }
+            if executed < count && self.at_breakpoint() {
+                return Ok(Some(Stop::Breakpoint(self.get_current_address())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Executes a single instruction, but runs a whole `call` as one step.
+    pub fn step_over(&mut self) -> Result<Option<Stop>, VirtualMachineError> {
+        let depth = self.cpu.backtrace().len();
+        self.run_while(|vm| vm.cpu.backtrace().len() > depth)
+    }
+
+    /// Runs the program until the current function returns, see [`CPU::backtrace`].
+    pub fn finish(&mut self) -> Result<Option<Stop>, VirtualMachineError> {
+        let depth = self.cpu.backtrace().len();
+        self.run_while(|vm| vm.cpu.backtrace().len() >= depth)
+    }
+
+    /// Runs the program until it stops, fails or reaches a breakpoint, a failure is printed to the
+    /// standard error; see [`VirtualMachine::resume`] to handle it.
+    pub fn run(&mut self) {
+        if let Err(err) = self.resume() {
+            eprintln!("The program has faulted at step {}: {:?}", self.steps, err);
+        }
+    }
+
+    /// Runs the program until it stops or reaches a breakpoint.
+    ///
+    /// The current instruction is always executed, so the execution can be resumed from a breakpoint.
+    pub fn resume(&mut self) -> Result<Option<Stop>, VirtualMachineError> {
+        self.run_while(|_| true)
+    }
+
+    /// Runs the program until it stops or reaches an address greater or equal to `at`.
+    pub fn run_until(&mut self, at: u16) -> Result<Option<Stop>, VirtualMachineError> {
+        self.run_while(|vm| vm.get_current_address() < at)
+    }
+
+    // Executes at least one instruction and continues while `condition` holds
+    fn run_while<F>(&mut self, condition: F) -> Result<Option<Stop>, VirtualMachineError>
+        where F: Fn(&Self) -> bool {
+        loop {
+            if let Some(stop) = self.next_step()? {
+                return Ok(Some(stop));
+            }
+            if !condition(self) {
+                return Ok(None);
+            }
+            if self.at_breakpoint() {
+                return Ok(Some(Stop::Breakpoint(self.get_current_address())));
+            }
+        }
+    }
+
+    /// Sets a breakpoint, returns `false` if it was already set.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Removes a breakpoint, returns `false` if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Removes all breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Replaces the code at `address` with a native function, returns `false` if another hook
+    /// was replaced.
+    ///
+    /// When the program counter reaches `address`, the hook runs instead of the instruction and
+    /// counts as one step; the program continues at the returned address. A hook at the start of a
+    /// function returns like `ret` does, with the address popped from the stack:
+    ///
+    /// ```
+    /// # use synacor_vm::{CPUError, VirtualMachine};
+    /// let mut vm = VirtualMachine::default();
+    /// vm.add_hook(0x178B, Box::new(|cpu| {
+    ///     cpu.write_register(0, 6)?;
+    ///     cpu.pop_stack().ok_or(CPUError::PopFromEmptyStack)
+    /// }));
+    /// ```
+    pub fn add_hook(&mut self, address: u16, hook: Hook) -> bool {
+        self.hooks.insert(address, hook).is_none()
+    }
+
+    /// Removes the hook, returns `false` if there was no hook at `address`.
+    pub fn remove_hook(&mut self, address: u16) -> bool {
+        self.hooks.remove(&address).is_some()
+    }
+
+    /// Returns the addresses of the hooks in the ascending order.
+    pub fn hooks(&self) -> impl Iterator<Item=u16> + '_ {
+        self.hooks.keys().copied()
+    }
+
+    /// Returns the breakpoints in the ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item=u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.breakpoints.contains(&self.get_current_address())
+    }
+
+    /// Enables or disables the strict spec-conformance mode of the CPU, see [`CPU::set_strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.cpu.set_strict(strict);
+    }
+
+    /// Prints the registers to the standard output.
     pub fn dump_registry(&self) {
         println!(r#"--- Registers ---
 {}
@@ -88,6 +569,18 @@ impl VirtualMachine {
                  ));
     }
 
+    /// Prints the shadow call stack to the standard output, the innermost frame goes first.
+    pub fn dump_backtrace(&self) {
+        println!("--- Backtrace ---");
+        for (number, frame) in self.cpu.backtrace().iter().rev().enumerate() {
+            println!("#{:<3} {:#06X} called from {:#06X} (r0: {:#06X}, r1: {:#06X}, r2: {:#06X})",
+                     number, frame.target, frame.call_site,
+                     frame.arguments[0], frame.arguments[1], frame.arguments[2]);
+        }
+        println!("#{:<3} {:#06X} <- current address", self.cpu.backtrace().len(), self.get_current_address());
+    }
+
+    /// Returns the address of the next instruction to execute.
     pub fn get_current_address(&self) -> u16 {
         self.cpu.get_current_address()
     }
@@ -111,6 +604,7 @@ impl From<MemoryError> for VirtualMachineError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::terminal::Capture;
     use std::io;
     use std::path::PathBuf;
 
@@ -156,7 +650,7 @@ mod tests {
         let path = path.to_str().unwrap();
 
         let binary = VirtualMachine::get_binary_from_path(path)
-            .expect(format!("The file must exist: {}", path).as_str());
+            .unwrap_or_else(|_| panic!("The file must exist: {}", path));
         assert_eq!(binary.len() as u64, fs::metadata(path)?.len() / 2); // length in u8 divided by 2
         assert_eq!(binary[0], 0x0015);
         assert_eq!(binary[1], 0x0015);
@@ -169,7 +663,7 @@ mod tests {
     #[test]
     fn test_load_binary_small() {
         let mut vm = VirtualMachine::default();
-        let _ = vm.load_binary(|| {
+        vm.load_binary(|| {
             vec![0x0015, 0x0015, 0x0013, 0x0057]
         }).expect("The binary should load without errors");
 
@@ -180,6 +674,328 @@ mod tests {
         assert_eq!(vm_memory.read_memory(3), Some(0x0057));
     }
 
+    // 0: call 6; 2: noop; 3: noop; 4: halt; 6: call 11; 8: noop; 9: noop; 10: ret; 11: noop; 12: ret
+    fn load_calls(vm: &mut VirtualMachine) {
+        vm.load_binary(|| {
+            vec![17, 6, 21, 21, 0, 0, 17, 11, 21, 21, 18, 21, 18]
+        }).expect("The binary should load without errors");
+    }
+
+    #[test]
+    fn test_step() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+
+        assert_eq!(vm.step(2).expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 11);
+        assert_eq!(vm.step(100).expect("The program must halt"), Some(Stop::Halted));
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+
+        assert_eq!(vm.step_over().expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 2);
+        assert!(vm.cpu.backtrace().is_empty());
+
+        assert_eq!(vm.step_over().expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 3);
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        vm.step(2).expect("The program must not fail");
+        assert_eq!(vm.get_current_address(), 11);
+
+        assert_eq!(vm.finish().expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 8);
+        assert_eq!(vm.finish().expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 2);
+        assert_eq!(vm.finish().expect("The program must halt outside of functions"), Some(Stop::Halted));
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        assert!(vm.add_hook(6, Box::new(|cpu| {
+            cpu.write_register(0, 42)?;
+            cpu.pop_stack().ok_or(CPUError::PopFromEmptyStack)
+        })));
+        assert_eq!(vm.hooks().collect::<Vec<u16>>(), [6]);
+
+        assert_eq!(vm.step(2).expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 2);
+        assert_eq!(vm.cpu.read_register(0), Some(42));
+        assert!(vm.cpu.backtrace().is_empty());
+        assert_eq!(vm.step(100).expect("The program must halt"), Some(Stop::Halted));
+        assert_eq!(vm.steps(), 5);
+
+        assert!(vm.remove_hook(6));
+        assert!(!vm.remove_hook(6));
+        vm.add_hook(0, Box::new(|_| Err(CPUError::PopFromEmptyStack)));
+        vm.cpu.set_current_address(0);
+        assert!(matches!(vm.next_step(), Err(VirtualMachineError::CPUFault(CPUError::PopFromEmptyStack))));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        assert!(vm.add_breakpoint(8));
+        assert!(vm.add_breakpoint(3));
+        assert!(!vm.add_breakpoint(3));
+        assert_eq!(vm.breakpoints().collect::<Vec<_>>(), [3, 8]);
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::Breakpoint(8)));
+        assert_eq!(vm.get_current_address(), 8);
+        assert_eq!(vm.step_over().expect("The program must not fail"), None);
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::Breakpoint(3)));
+        assert_eq!(vm.get_current_address(), 3);
+
+        assert!(vm.remove_breakpoint(8));
+        assert!(!vm.remove_breakpoint(8));
+        assert_eq!(vm.resume().expect("The program must halt"), Some(Stop::Halted));
+
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        vm.add_breakpoint(3);
+        assert_eq!(vm.step(100).expect("The program must not fail"), Some(Stop::Breakpoint(3)));
+        let steps = vm.steps();
+        assert_eq!(vm.step(1).expect("The program must not fail"), None);
+        assert_eq!(vm.steps(), steps + 1);
+    }
+
+    struct Lines(Vec<Input>);
+
+    impl InputSource for Lines {
+        fn read_line(&mut self) -> Input {
+            if self.0.is_empty() { Input::End } else { self.0.remove(0) }
+        }
+    }
+
+    #[test]
+    fn test_input_output() {
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.set_input(Lines(vec![
+            Input::Line(String::from("hi\n")),
+            Input::Line(String::from("yo")),
+            Input::Interrupt,
+        ]));
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::Interrupted));
+        assert_eq!(vm.get_current_address(), 0);
+        assert_eq!(output.contents(), b"hi\nyo\n");
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.get_current_address(), 0);
+    }
+
+    #[test]
+    fn test_script() {
+        // 0: in r0; 2: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.set_input(Lines(vec![Input::Line(String::from("typed\n"))]));
+        vm.add_script(vec![String::from("north"), String::from("take lamp\n")]);
+        vm.set_script_echo(true);
+        assert_eq!(vm.pending_script(), 2);
+
+        assert_eq!(vm.step(4).expect("The program must not fail"), None);
+        assert_eq!(vm.pending_script(), 1);
+        assert_eq!(vm.cpu.read_register(0), Some(b'o' as u16));
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.pending_script(), 0);
+        assert_eq!(output.text(), "north\ntake lamp\n");
+        assert_eq!(vm.cpu.read_register(0), Some(b'\n' as u16));
+    }
+
+    #[test]
+    fn test_host_command() {
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.set_input(Lines(Vec::new()));
+        vm.set_host_prefix(Some("!"));
+        vm.add_script(vec![String::from("  !save vault "), String::from("a"), String::from("!regs")]);
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::HostCommand(String::from("save vault"))));
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::HostCommand(String::from("regs"))));
+        assert_eq!(output.text(), "a\n");
+
+        vm.set_host_prefix(None);
+        vm.add_script(vec![String::from("!")]);
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(output.text(), "a\n!\n");
+    }
+
+    #[test]
+    fn test_undo() {
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        vm.set_output(Capture::default());
+        vm.set_input(Lines(Vec::new()));
+        vm.set_checkpoint_limit(DEFAULT_CHECKPOINTS);
+        vm.add_script(vec![String::from("a"), String::from("b"), String::from("c")]);
+        let snapshot = vm.snapshot();
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.checkpoints(), 3);
+        assert_eq!(vm.undo(2).expect("The checkpoint must be restored"), 2);
+        assert_eq!(vm.checkpoints(), 1);
+        assert_eq!(vm.steps(), 6);
+        assert_eq!(vm.cpu.pending_input(), 0);
+        assert_eq!(vm.cpu.read_register(0), Some(b'\n' as u16));
+
+        assert_eq!(vm.undo(5).expect("The checkpoint must be restored"), 1);
+        assert_eq!(vm.steps(), 0);
+        assert_eq!(vm.undo(1).expect("Nothing to undo"), 0);
+
+        vm.set_checkpoint_limit(1);
+        vm.add_script(vec![String::from("a"), String::from("b")]);
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.checkpoints(), 1);
+
+        vm.restore(&snapshot).expect("The snapshot must be restored");
+        assert_eq!(vm.checkpoints(), 0);
+        assert_eq!(vm.undo(1).expect("Nothing to undo"), 0);
+    }
+
+    #[test]
+    fn test_rewind() {
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.set_input(Lines(Vec::new()));
+        vm.set_timeline(4, 2);
+        vm.set_checkpoint_limit(DEFAULT_CHECKPOINTS);
+        vm.add_script(vec![String::from("ab"), String::from("cd")]);
+        let events = Rc::new(RefCell::new(Events::default()));
+        vm.add_observer(events.clone());
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.steps(), 18);
+        assert_eq!(events.borrow().0.len(), 12);
+        assert_eq!(vm.timeline().steps().collect::<Vec<_>>(), [12, 16]);
+        assert!(matches!(vm.rewind(11), Err(VirtualMachineError::OutOfTimeline(11))));
+        assert!(matches!(vm.rewind(19), Err(VirtualMachineError::OutOfTimeline(19))));
+
+        assert_eq!(vm.rewind(13).expect("The step is in the timeline"), 12);
+        assert_eq!(vm.steps(), 13);
+        assert_eq!(vm.cpu.read_register(0), Some(b'd' as u16));
+        assert_eq!(vm.timeline().steps().collect::<Vec<_>>(), [12]);
+        assert_eq!(vm.checkpoints(), 2);
+        assert_eq!(events.borrow().0.len(), 12);
+
+        vm.add_script(vec![String::from("x")]);
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(output.text(), "ab\ncd\nd\nx\n");
+    }
+
+    #[test]
+    fn test_rewind_hooks() {
+        // 0: in r0; 2: noop; 3: out r0; 5: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 21, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        vm.set_output(Capture::default());
+        vm.set_input(Lines(Vec::new()));
+        vm.set_timeline(4, 3);
+        vm.add_hook(2, Box::new(|cpu| {
+            let count = cpu.read_register(1).unwrap_or_default();
+            cpu.write_register(1, count + 1)?;
+            Ok(3)
+        }));
+        vm.add_script(vec![String::from("ab"), String::from("cd")]);
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(vm.cpu.read_register(1), Some(6));
+        assert_eq!(vm.rewind(18).expect("The step is in the timeline"), 16);
+        assert_eq!(vm.get_current_address(), 3);
+        assert_eq!(vm.cpu.read_register(0), Some(b'd' as u16));
+        assert_eq!(vm.cpu.read_register(1), Some(5));
+    }
+
+    #[derive(Default)]
+    struct Events(Vec<(char, u64, u8)>);
+
+    impl Observer for Events {
+        fn output(&mut self, step: u64, c: u8) {
+            self.0.push(('<', step, c));
+        }
+
+        fn input(&mut self, step: u64, c: u8) {
+            self.0.push(('>', step, c));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let mut vm = VirtualMachine::default();
+        vm.load_binary(|| vec![20, 32768, 19, 32768, 6, 0])
+            .expect("The binary should load without errors");
+        vm.set_output(Capture::default());
+        vm.set_input(Lines(vec![Input::Line(String::from("a"))]));
+        let events = Rc::new(RefCell::new(Events::default()));
+        vm.add_observer(events.clone());
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::EndOfInput));
+        assert_eq!(events.borrow().0, [('>', 0, b'a'), ('<', 1, b'a'), ('>', 3, b'\n'), ('<', 4, b'\n')]);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        vm.set_step_limit(Some(3));
+
+        assert_eq!(vm.resume().expect("The program must not fail"), Some(Stop::StepLimit));
+        assert_eq!(vm.steps(), 3);
+        assert_eq!(vm.get_current_address(), 12);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut vm = VirtualMachine::default();
+        load_calls(&mut vm);
+        vm.step(2).expect("The program must not fail");
+        vm.cpu.write_register(7, 25734).expect("The register must exist");
+        let snapshot = vm.snapshot();
+
+        vm.cpu.set_value_in_address(11, 0).expect("The address must exist");
+        vm.step(3).expect("The program must not fail");
+        vm.restore(&snapshot).expect("The snapshot must be restored");
+
+        assert_eq!(vm.steps(), 2);
+        assert_eq!(vm.get_current_address(), 11);
+        assert_eq!(vm.cpu.read_register(7), Some(25734));
+        assert_eq!(vm.cpu.get_value_from_address(11).ok(), Some(21));
+        assert_eq!(vm.cpu.backtrace().len(), 2);
+        assert_eq!(vm.step_over().expect("The program must not fail"), None);
+        assert_eq!(vm.get_current_address(), 12);
+    }
+
     #[test]
     fn test_load_binary_big() {
         let mut vm = VirtualMachine::default();